@@ -4,6 +4,7 @@ import { BillingService } from './billing.service';
 class PrepareSubscribeDto {
   subscriber!: string;
   planId!: number;
+  couponCode?: string;
 }
 
 class PrepareCancelDto {
@@ -47,6 +48,7 @@ export class BillingCheckoutController {
       projectId,
       body.subscriber,
       Number(body.planId),
+      body.couponCode,
     );
   }
 
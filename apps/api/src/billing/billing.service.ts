@@ -87,6 +87,8 @@ export class BillingService {
         return 'Renewal is too early';
       case 13:
         return 'Invalid page size';
+      case 30:
+        return 'Plan is full';
       default:
         return `Contract rejected action (code #${code})`;
     }
@@ -120,6 +122,7 @@ export class BillingService {
         case 9:
         case 10:
         case 12:
+        case 30:
           return new BadRequestException(this.contractCodeToMessage(code));
         default:
           return new BadRequestException(
@@ -165,11 +168,6 @@ export class BillingService {
     throw new BadRequestException('Unexpected token amount format');
   }
 
-  private isAllowanceError(error: unknown): boolean {
-    const message = error instanceof Error ? error.message : String(error);
-    return message.toLowerCase().includes('not enough allowance');
-  }
-
   private resolveProjectPaymentTokenContractId(project: ProjectDocument): string | null {
     if (project.paymentTokenContractId) {
       return project.paymentTokenContractId;
@@ -321,10 +319,14 @@ export class BillingService {
     }
 
     const latestLedger = await this.soroban.getLatestLedgerSequence();
+    const keeper = this.soroban.getBackendKeypair().publicKey();
     const pageSize = 50;
     let offset = 0;
     let renewed = 0;
-    let skippedAllowance = 0;
+    let pastDue = 0;
+    let suspended = 0;
+    let expired = 0;
+    let skipped = 0;
     let failed = 0;
     let scanned = 0;
     let pages = 0;
@@ -341,21 +343,55 @@ export class BillingService {
       const items = Array.isArray(page) ? page.map((s) => this.mapSubscription(s)) : [];
       if (!items.length) break;
 
-      for (const item of items) {
-        scanned += 1;
-        if (!item.active) continue;
-        if (item.nextRenewalLedger > latestLedger) continue;
+      scanned += items.length;
+      // Subscriptions with a scheduled cancellation end instead of renewing.
+      // Ones past their grace period only read as suspended until a renewal
+      // stores it and frees their seat; the contract skips them after that.
+      const due = items.filter(
+        (item) =>
+          (item.active &&
+            item.cancelAtLedger === null &&
+            item.nextRenewalLedger <= latestLedger) ||
+          item.status === 'Suspended',
+      );
+      if (due.length) {
         try {
-          await this.soroban.invokeSigned(project.subscriptionContractId, 'renew', [
-            { type: 'address', value: item.subscriber },
-          ]);
-          renewed += 1;
-        } catch (error) {
-          if (this.isAllowanceError(error)) {
-            skippedAllowance += 1;
-            continue;
+          const { returnValue } = await this.soroban.invokeSigned(
+            project.subscriptionContractId,
+            'renew_many',
+            [
+              { type: 'address', value: keeper },
+              {
+                type: 'vec',
+                value: due.map((item) => ({
+                  type: 'address' as const,
+                  value: item.subscriber,
+                })),
+              },
+            ],
+          );
+          // A failed charge doesn't fail the call; it shows up as the status
+          // the subscription moved to.
+          for (const result of Array.isArray(returnValue) ? returnValue : []) {
+            switch (this.parseRenewalStatus(result?.outcome)) {
+              case 'Active':
+                renewed += 1;
+                break;
+              case 'PastDue':
+                pastDue += 1;
+                break;
+              case 'Suspended':
+                suspended += 1;
+                break;
+              case 'Expired':
+                expired += 1;
+                break;
+              default:
+                skipped += 1;
+            }
           }
-          failed += 1;
+        } catch {
+          failed += due.length;
         }
       }
       offset += pageSize;
@@ -368,12 +404,25 @@ export class BillingService {
       subscriptionContractId: project.subscriptionContractId,
       scanned,
       renewed,
-      skippedAllowance,
+      pastDue,
+      suspended,
+      expired,
+      skipped,
       failed,
       latestLedger,
     };
   }
 
+  /**
+   * Status a `renew_many` entry ended in, or null when it was skipped.
+   * `Processed(status)` decodes as `['Processed', ['Active']]`.
+   */
+  private parseRenewalStatus(outcome: unknown): string | null {
+    if (!Array.isArray(outcome) || outcome[0] !== 'Processed') return null;
+    const status = outcome[1];
+    return String(Array.isArray(status) ? status[0] : status);
+  }
+
   async createPlan(
     ownerPublicKey: string,
     projectId: string,
@@ -531,7 +580,9 @@ export class BillingService {
     }
 
     const plans = Array.isArray(plansData)
-      ? plansData.map((p) => this.mapPlan(p))
+      ? plansData
+          .map((p) => this.mapPlan(p))
+          .sort((a, b) => a.displayOrder - b.displayOrder || a.id - b.id)
       : [];
     let subscription: ReturnType<typeof this.mapSubscription> | null = null;
     let remainingAllowanceStroops: string | null = null;
@@ -592,7 +643,12 @@ export class BillingService {
     };
   }
 
-  async prepareSubscribe(projectId: string, subscriber: string, planId: number) {
+  async prepareSubscribe(
+    projectId: string,
+    subscriber: string,
+    planId: number,
+    couponCode?: string,
+  ) {
     const project = await this.assertProjectForCheckout(projectId);
     const contractId = project.subscriptionContractId;
     if (!contractId) {
@@ -617,6 +673,9 @@ export class BillingService {
         [
           { type: 'address', value: address },
           { type: 'u32', value: planId },
+          couponCode?.trim()
+            ? { type: 'symbol', value: couponCode.trim() }
+            : { type: 'void' },
         ],
       );
     } catch (error) {
@@ -780,6 +839,12 @@ export class BillingService {
       periodLedgers: Number(plan.period_ledgers ?? plan.periodLedgers ?? 0),
       priceStroops: String(plan.price_stroops ?? plan.priceStroops ?? '0'),
       active: Boolean(plan.active),
+      description: plan.description ?? null,
+      metadataUri: plan.metadata_uri ?? plan.metadataUri ?? null,
+      displayOrder: Number(plan.display_order ?? plan.displayOrder ?? 0),
+      maxActiveSubscribers: Number(
+        plan.max_active_subscribers ?? plan.maxActiveSubscribers ?? 0,
+      ),
     };
   }
 
@@ -791,7 +856,17 @@ export class BillingService {
       nextRenewalLedger: Number(
         sub.next_renewal_ledger ?? sub.nextRenewalLedger ?? 0,
       ),
+      nextRenewalTimestamp:
+        sub.next_renewal_timestamp != null
+          ? Number(sub.next_renewal_timestamp)
+          : null,
       active: Boolean(sub.active),
+      status:
+        sub.status != null
+          ? String(Array.isArray(sub.status) ? sub.status[0] : sub.status)
+          : null,
+      cancelAtLedger:
+        sub.cancel_at_ledger != null ? Number(sub.cancel_at_ledger) : null,
     };
   }
 }
@@ -8,7 +8,9 @@ type ScArg =
   | { type: 'bool'; value: boolean }
   | { type: 'address'; value: string }
   | { type: 'symbol'; value: string }
-  | { type: 'string'; value: string };
+  | { type: 'string'; value: string }
+  | { type: 'vec'; value: ScArg[] }
+  | { type: 'void' };
 type InvokeSpec = {
   contractId: string;
   method: string;
@@ -58,6 +60,10 @@ export class SorobanService {
         return StellarSdk.nativeToScVal(arg.value, { type: 'symbol' });
       case 'string':
         return StellarSdk.nativeToScVal(arg.value, { type: 'string' });
+      case 'vec':
+        return StellarSdk.xdr.ScVal.scvVec(arg.value.map((a) => this.toScVal(a)));
+      case 'void':
+        return StellarSdk.xdr.ScVal.scvVoid();
       default:
         throw new Error('Unsupported argument type');
     }
@@ -115,7 +121,7 @@ export class SorobanService {
     contractId: string,
     method: string,
     args: ScArg[],
-  ): Promise<{ txHash: string }> {
+  ): Promise<{ txHash: string; returnValue: unknown }> {
     const server = this.getRpcServer();
     const source = this.getBackendKeypair();
     const tx = await this.buildBackendInvokeTx(contractId, method, args);
@@ -133,7 +139,13 @@ export class SorobanService {
         `Transaction failed or not confirmed (${(polled as { status?: string }).status ?? 'unknown'})`,
       );
     }
-    return { txHash: send.hash };
+    const success = polled as { returnValue?: StellarSdk.xdr.ScVal };
+    return {
+      txHash: send.hash,
+      returnValue: success.returnValue
+        ? StellarSdk.scValToNative(success.returnValue)
+        : null,
+    };
   }
 
   async prepareUnsignedInvokeXdr(
@@ -120,9 +120,10 @@ export default function MembershipsPage() {
         title: "Renew run completed",
         description:
           `Renewed ${result.renewed} of ${result.scanned} subscriptions` +
-          (result.skippedAllowance
-            ? `, skipped allowance: ${result.skippedAllowance}`
-            : "") +
+          (result.pastDue ? `, past due: ${result.pastDue}` : "") +
+          (result.suspended ? `, suspended: ${result.suspended}` : "") +
+          (result.expired ? `, expired: ${result.expired}` : "") +
+          (result.skipped ? `, skipped: ${result.skipped}` : "") +
           (result.failed ? `, failed: ${result.failed}` : "") +
           ".",
         variant: "success",
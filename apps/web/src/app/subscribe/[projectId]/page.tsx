@@ -590,6 +590,11 @@ function CheckoutContent() {
                         <p className="font-inter text-sm font-semibold text-text-primary">
                           {plan.name || `Plan #${plan.id}`}
                         </p>
+                        {plan.description ? (
+                          <p className="font-outfit text-xs text-text-secondary">
+                            {plan.description}
+                          </p>
+                        ) : null}
                         <p className="font-outfit text-xs text-text-secondary">
                           Price: {(Number(plan.priceStroops) / 10_000_000).toFixed(2)}{" "}
                           {data.project.paymentCurrency}
@@ -643,10 +648,14 @@ function CheckoutContent() {
                 <p className="text-sm text-text-secondary">
                   Next renewal:{" "}
                   <span title={`Ledger #${activeSubscription?.nextRenewalLedger ?? "-"}`}>
-                    {formatLedgerEstimate(
-                      activeSubscription?.nextRenewalLedger ?? 0,
-                      latestLedger,
-                    )}
+                    {activeSubscription?.nextRenewalTimestamp
+                      ? new Date(
+                          activeSubscription.nextRenewalTimestamp * 1000,
+                        ).toLocaleString()
+                      : formatLedgerEstimate(
+                          activeSubscription?.nextRenewalLedger ?? 0,
+                          latestLedger,
+                        )}
                   </span>
                 </p>
                 <div className="flex flex-col gap-3 pt-2 sm:flex-row">
@@ -33,6 +33,10 @@ export interface ContractPlan {
   periodLedgers: number;
   priceStroops: string;
   active: boolean;
+  description?: string | null;
+  metadataUri?: string | null;
+  displayOrder?: number;
+  maxActiveSubscribers?: number;
 }
 
 export interface ContractSubscription {
@@ -40,6 +44,7 @@ export interface ContractSubscription {
   planId: number;
   startedLedger: number;
   nextRenewalLedger: number;
+  nextRenewalTimestamp?: number | null;
   active: boolean;
 }
 
@@ -264,7 +269,15 @@ export async function listSubscriptions(
 export async function renewDueSubscriptions(
   token: string,
   projectId: string,
-): Promise<{ renewed: number; scanned: number; skippedAllowance?: number; failed?: number }> {
+): Promise<{
+  renewed: number;
+  scanned: number;
+  pastDue?: number;
+  suspended?: number;
+  expired?: number;
+  skipped?: number;
+  failed?: number;
+}> {
   const res = await fetch(`${API_URL}/projects/${projectId}/renew-due`, {
     method: "POST",
     headers: authHeaders(token),
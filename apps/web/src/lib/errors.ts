@@ -12,6 +12,7 @@ const CONTRACT_ERROR_MESSAGES: Record<number, string> = {
   11: "Invalid price.",
   12: "Renewal is too early.",
   13: "Invalid page size.",
+  30: "This plan is full.",
 };
 
 export function toDisplayError(error: unknown, fallback: string): string {
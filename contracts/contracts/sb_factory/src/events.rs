@@ -0,0 +1,63 @@
+use soroban_sdk::{contractevent, Address, BytesN, String};
+
+/// Version of the event payload layout. Bumped whenever a field is added,
+/// removed or changes meaning so indexers can branch on it.
+pub const EVENT_VERSION: u32 = 1;
+
+#[contractevent(topics = ["factory", "initialized"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Initialized {
+    #[topic]
+    pub admin: Address,
+    pub wasm_hash: BytesN<32>,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["factory", "wasm_hash_changed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WasmHashChanged {
+    pub wasm_hash: BytesN<32>,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["factory", "deployed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Deployed {
+    #[topic]
+    pub owner: Address,
+    pub project_id: String,
+    pub contract_id: Address,
+    pub wasm_hash: BytesN<32>,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["factory", "instance_upgraded"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InstanceUpgraded {
+    #[topic]
+    pub contract_id: Address,
+    pub project_id: String,
+    pub wasm_hash: BytesN<32>,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["factory", "instance_admin_changed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InstanceAdminChanged {
+    #[topic]
+    pub contract_id: Address,
+    pub project_id: String,
+    pub owner: Address,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["factory", "instance_payment_token_changed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InstancePaymentTokenChanged {
+    #[topic]
+    pub contract_id: Address,
+    pub project_id: String,
+    pub payment_token: Address,
+    pub effective_ledger: u32,
+    pub version: u32,
+}
@@ -0,0 +1,414 @@
+#![no_std]
+
+mod events;
+
+use core::cmp::min;
+use events::EVENT_VERSION;
+use soroban_sdk::{
+    contract, contracterror, contractimpl, contracttype, panic_with_error, xdr::ToXdr, Address,
+    BytesN, Env, IntoVal, String, Symbol, Vec,
+};
+
+const INSTANCE_LIFETIME_THRESHOLD: u32 = 100_000;
+const INSTANCE_BUMP_AMOUNT: u32 = 200_000;
+const PERSISTENT_LIFETIME_THRESHOLD: u32 = 100_000;
+const PERSISTENT_BUMP_AMOUNT: u32 = 200_000;
+const MAX_PAGE_SIZE: u32 = 50;
+
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum FactoryError {
+    AlreadyInitialized = 1,
+    NotInitialized = 2,
+    Unauthorized = 3,
+    InvalidPageSize = 4,
+    ProjectAlreadyExists = 5,
+    ProjectNotFound = 6,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DataKey {
+    Admin,
+    WasmHash,
+    DeploymentCount(Address),
+    Deployment(Address, u32),
+    ProjectCount,
+    ProjectIndex(u32),
+    Project(Address, String),
+    ContractProject(Address),
+}
+
+/// Registry entry for a deployed `sb_subscription` instance. `deployer` is
+/// the address that deployed it, which `get_project` looks it up by along
+/// with `project_id`. `owner` is the instance admin and `payment_token` the
+/// token it charges in from `payment_token_ledger` on, as last reported by
+/// the instance.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Project {
+    pub project_id: String,
+    pub contract_id: Address,
+    pub deployer: Address,
+    pub owner: Address,
+    pub payment_token: Address,
+    pub payment_token_ledger: u32,
+    pub wasm_hash: BytesN<32>,
+}
+
+#[contract]
+pub struct SbFactory;
+
+fn bump_instance_ttl(env: &Env) {
+    env.storage()
+        .instance()
+        .extend_ttl(INSTANCE_LIFETIME_THRESHOLD, INSTANCE_BUMP_AMOUNT);
+}
+
+fn bump_persistent_ttl(env: &Env, key: &DataKey) {
+    env.storage().persistent().extend_ttl(
+        key,
+        PERSISTENT_LIFETIME_THRESHOLD,
+        PERSISTENT_BUMP_AMOUNT,
+    );
+}
+
+fn read_admin(env: &Env) -> Address {
+    bump_instance_ttl(env);
+    env.storage()
+        .instance()
+        .get(&DataKey::Admin)
+        .unwrap_or_else(|| panic_with_error!(env, FactoryError::NotInitialized))
+}
+
+fn assert_admin(env: &Env, caller: &Address) {
+    if read_admin(env) != *caller {
+        panic_with_error!(env, FactoryError::Unauthorized);
+    }
+}
+
+fn read_wasm_hash(env: &Env) -> BytesN<32> {
+    bump_instance_ttl(env);
+    env.storage()
+        .instance()
+        .get(&DataKey::WasmHash)
+        .unwrap_or_else(|| panic_with_error!(env, FactoryError::NotInitialized))
+}
+
+fn read_deployment_count(env: &Env, owner: &Address) -> u32 {
+    env.storage()
+        .persistent()
+        .get(&DataKey::DeploymentCount(owner.clone()))
+        .unwrap_or(0)
+}
+
+fn read_project_count(env: &Env) -> u32 {
+    bump_instance_ttl(env);
+    env.storage()
+        .instance()
+        .get(&DataKey::ProjectCount)
+        .unwrap_or(0)
+}
+
+fn read_project(env: &Env, owner: &Address, project_id: &String) -> Project {
+    let key = DataKey::Project(owner.clone(), project_id.clone());
+    let project = env
+        .storage()
+        .persistent()
+        .get(&key)
+        .unwrap_or_else(|| panic_with_error!(env, FactoryError::ProjectNotFound));
+    bump_persistent_ttl(env, &key);
+    project
+}
+
+fn read_contract_project(env: &Env, contract_id: &Address) -> (Address, String) {
+    let key = DataKey::ContractProject(contract_id.clone());
+    let project_key = env
+        .storage()
+        .persistent()
+        .get(&key)
+        .unwrap_or_else(|| panic_with_error!(env, FactoryError::ProjectNotFound));
+    bump_persistent_ttl(env, &key);
+    project_key
+}
+
+/// Reads the project of `contract_id`, which must be the invoker, for an
+/// update it reports.
+fn read_reported_project(env: &Env, contract_id: &Address) -> (DataKey, Project) {
+    contract_id.require_auth();
+    let (owner, project_id) = read_contract_project(env, contract_id);
+    let project = read_project(env, &owner, &project_id);
+    (DataKey::Project(owner, project_id), project)
+}
+
+fn write_project(env: &Env, key: &DataKey, project: &Project) {
+    env.storage().persistent().set(key, project);
+    bump_persistent_ttl(env, key);
+}
+
+/// `sha256((owner, project_id) XDR)`, so a project's instance always lands on
+/// the same address and can be computed off-chain before it is deployed.
+/// Project ids are scoped to their owner, so nobody can take another
+/// owner's address by deploying the same id first.
+fn deployment_salt(env: &Env, owner: &Address, project_id: &String) -> BytesN<32> {
+    env.crypto()
+        .sha256(&(owner.clone(), project_id.clone()).to_xdr(env))
+        .into()
+}
+
+#[contractimpl]
+impl SbFactory {
+    pub fn init(env: Env, admin: Address, wasm_hash: BytesN<32>) {
+        if env.storage().instance().has(&DataKey::Admin) {
+            panic_with_error!(&env, FactoryError::AlreadyInitialized);
+        }
+
+        admin.require_auth();
+        env.storage().instance().set(&DataKey::Admin, &admin);
+        env.storage().instance().set(&DataKey::WasmHash, &wasm_hash);
+        bump_instance_ttl(&env);
+
+        events::Initialized {
+            admin,
+            wasm_hash,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    /// Points future deployments at a new `sb_subscription` build. Existing
+    /// instances are not touched; their owners upgrade them separately.
+    pub fn set_wasm_hash(env: Env, caller: Address, wasm_hash: BytesN<32>) {
+        caller.require_auth();
+        assert_admin(&env, &caller);
+
+        env.storage().instance().set(&DataKey::WasmHash, &wasm_hash);
+        bump_instance_ttl(&env);
+
+        events::WasmHashChanged {
+            wasm_hash,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    pub fn get_wasm_hash(env: Env) -> BytesN<32> {
+        read_wasm_hash(&env)
+    }
+
+    /// Deploys a new `sb_subscription` instance for `owner`'s `project_id`
+    /// with `owner` as its admin, initializes it in the same invocation so nobody can
+    /// front-run `init`, and registers it. The instance reports its upgrades
+    /// back to this contract.
+    pub fn deploy(
+        env: Env,
+        owner: Address,
+        project_id: String,
+        payment_token: Address,
+        treasury: Address,
+    ) -> Address {
+        owner.require_auth();
+
+        let project_key = DataKey::Project(owner.clone(), project_id.clone());
+        if env.storage().persistent().has(&project_key) {
+            panic_with_error!(&env, FactoryError::ProjectAlreadyExists);
+        }
+
+        let wasm_hash = read_wasm_hash(&env);
+        let contract_id = env
+            .deployer()
+            .with_current_contract(deployment_salt(&env, &owner, &project_id))
+            .deploy_v2(wasm_hash.clone(), ());
+        env.invoke_contract::<()>(
+            &contract_id,
+            &Symbol::new(&env, "init"),
+            (owner.clone(), payment_token.clone(), treasury).into_val(&env),
+        );
+        env.invoke_contract::<()>(
+            &contract_id,
+            &Symbol::new(&env, "set_registry"),
+            (owner.clone(), Some(env.current_contract_address())).into_val(&env),
+        );
+
+        let index = read_deployment_count(&env, &owner);
+        let deployment_key = DataKey::Deployment(owner.clone(), index);
+        let count_key = DataKey::DeploymentCount(owner.clone());
+        env.storage()
+            .persistent()
+            .set(&deployment_key, &contract_id);
+        env.storage().persistent().set(&count_key, &(index + 1));
+        bump_persistent_ttl(&env, &deployment_key);
+        bump_persistent_ttl(&env, &count_key);
+
+        let project_count = read_project_count(&env);
+        let index_key = DataKey::ProjectIndex(project_count);
+        let contract_key = DataKey::ContractProject(contract_id.clone());
+        let project = Project {
+            project_id: project_id.clone(),
+            contract_id: contract_id.clone(),
+            deployer: owner.clone(),
+            owner: owner.clone(),
+            payment_token,
+            payment_token_ledger: env.ledger().sequence(),
+            wasm_hash: wasm_hash.clone(),
+        };
+        env.storage().persistent().set(&project_key, &project);
+        let registry_key = (owner.clone(), project_id.clone());
+        env.storage().persistent().set(&index_key, &registry_key);
+        env.storage().persistent().set(&contract_key, &registry_key);
+        env.storage()
+            .instance()
+            .set(&DataKey::ProjectCount, &(project_count + 1));
+        bump_persistent_ttl(&env, &project_key);
+        bump_persistent_ttl(&env, &index_key);
+        bump_persistent_ttl(&env, &contract_key);
+
+        events::Deployed {
+            owner,
+            project_id,
+            contract_id: contract_id.clone(),
+            wasm_hash,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+        contract_id
+    }
+
+    /// Called by a registered instance from its `upgrade`; the instance itself
+    /// must be the invoker, so records can't be rewritten by anyone else.
+    pub fn record_upgrade(env: Env, contract_id: Address, wasm_hash: BytesN<32>) {
+        let (key, mut project) = read_reported_project(&env, &contract_id);
+        project.wasm_hash = wasm_hash.clone();
+        write_project(&env, &key, &project);
+
+        events::InstanceUpgraded {
+            project_id: project.project_id,
+            contract_id,
+            wasm_hash,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    /// Called by a registered instance from its `accept_admin`. The project
+    /// stays listed under its `deployer`.
+    pub fn record_admin(env: Env, contract_id: Address, owner: Address) {
+        let (key, mut project) = read_reported_project(&env, &contract_id);
+        project.owner = owner.clone();
+        write_project(&env, &key, &project);
+
+        events::InstanceAdminChanged {
+            contract_id,
+            project_id: project.project_id,
+            owner,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    /// Called by a registered instance from its `schedule_payment_token`.
+    pub fn record_payment_token(
+        env: Env,
+        contract_id: Address,
+        payment_token: Address,
+        effective_ledger: u32,
+    ) {
+        let (key, mut project) = read_reported_project(&env, &contract_id);
+        project.payment_token = payment_token.clone();
+        project.payment_token_ledger = effective_ledger;
+        write_project(&env, &key, &project);
+
+        events::InstancePaymentTokenChanged {
+            contract_id,
+            project_id: project.project_id,
+            payment_token,
+            effective_ledger,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    /// Address `deploy` will create for `owner`'s `project_id`.
+    pub fn predict_address(env: Env, owner: Address, project_id: String) -> Address {
+        env.deployer()
+            .with_current_contract(deployment_salt(&env, &owner, &project_id))
+            .deployed_address()
+    }
+
+    /// `owner` is the address that deployed the project, kept as its
+    /// `deployer`.
+    pub fn get_project(env: Env, owner: Address, project_id: String) -> Project {
+        read_project(&env, &owner, &project_id)
+    }
+
+    pub fn get_project_by_contract(env: Env, contract_id: Address) -> Project {
+        let (owner, project_id) = read_contract_project(&env, &contract_id);
+        read_project(&env, &owner, &project_id)
+    }
+
+    pub fn get_project_count(env: Env) -> u32 {
+        read_project_count(&env)
+    }
+
+    pub fn list_projects(env: Env, offset: u32, limit: u32) -> Vec<Project> {
+        if limit > MAX_PAGE_SIZE {
+            panic_with_error!(&env, FactoryError::InvalidPageSize);
+        }
+        let mut result = Vec::new(&env);
+        let project_count = read_project_count(&env);
+        if limit == 0 || offset >= project_count {
+            return result;
+        }
+
+        let end = min(project_count, offset.saturating_add(limit));
+        let mut index = offset;
+        while index < end {
+            let index_key = DataKey::ProjectIndex(index);
+            let maybe_key: Option<(Address, String)> = env.storage().persistent().get(&index_key);
+            if let Some((owner, project_id)) = maybe_key {
+                let project_key = DataKey::Project(owner, project_id);
+                let maybe_project: Option<Project> = env.storage().persistent().get(&project_key);
+                if let Some(project) = maybe_project {
+                    result.push_back(project);
+                    bump_persistent_ttl(&env, &project_key);
+                }
+                bump_persistent_ttl(&env, &index_key);
+            }
+            index += 1;
+        }
+
+        result
+    }
+
+    pub fn get_deployment_count(env: Env, owner: Address) -> u32 {
+        read_deployment_count(&env, &owner)
+    }
+
+    pub fn list_deployments(env: Env, owner: Address, offset: u32, limit: u32) -> Vec<Address> {
+        if limit > MAX_PAGE_SIZE {
+            panic_with_error!(&env, FactoryError::InvalidPageSize);
+        }
+        let mut result = Vec::new(&env);
+        let deployment_count = read_deployment_count(&env, &owner);
+        if limit == 0 || offset >= deployment_count {
+            return result;
+        }
+
+        let end = min(deployment_count, offset.saturating_add(limit));
+        let mut index = offset;
+        while index < end {
+            let key = DataKey::Deployment(owner.clone(), index);
+            let maybe_contract: Option<Address> = env.storage().persistent().get(&key);
+            if let Some(contract_id) = maybe_contract {
+                result.push_back(contract_id);
+                bump_persistent_ttl(&env, &key);
+            }
+            index += 1;
+        }
+
+        result
+    }
+}
+
+mod test;
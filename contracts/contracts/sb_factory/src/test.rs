@@ -0,0 +1,270 @@
+#![cfg(test)]
+
+use crate::{events, FactoryError, Project, SbFactory, SbFactoryClient};
+use soroban_sdk::{
+    testutils::{Address as _, Events as _},
+    Address, BytesN, Env, Event as _, String,
+};
+
+mod subscription {
+    soroban_sdk::contractimport!(file = "../../target/wasm32v1-none/release/sb_subscription.wasm");
+}
+
+struct Setup<'a> {
+    factory: SbFactoryClient<'a>,
+    factory_id: Address,
+    admin: Address,
+    wasm_hash: BytesN<32>,
+    token: Address,
+}
+
+/// Registers the factory with the `sb_subscription` wasm uploaded.
+fn setup(env: &Env) -> Setup<'_> {
+    env.mock_all_auths();
+    let factory_id = env.register(SbFactory, ());
+    let factory = SbFactoryClient::new(env, &factory_id);
+    let admin = Address::generate(env);
+    // Uploading a wasm close to the network size limit costs more than the
+    // default test budget; that limit is checked on chain, not here.
+    env.cost_estimate().budget().reset_unlimited();
+    let wasm_hash = env.deployer().upload_contract_wasm(subscription::WASM);
+    let token = env
+        .register_stellar_asset_contract_v2(Address::generate(env))
+        .address();
+    factory.init(&admin, &wasm_hash);
+
+    Setup {
+        factory,
+        factory_id,
+        admin,
+        wasm_hash,
+        token,
+    }
+}
+
+#[test]
+fn deploy_initializes_instance_at_predicted_address() {
+    let env = Env::default();
+    let s = setup(&env);
+    let owner = Address::generate(&env);
+    let treasury = Address::generate(&env);
+    let project_id = String::from_str(&env, "proj-1");
+
+    let predicted = s.factory.predict_address(&owner, &project_id);
+    let contract_id = s.factory.deploy(&owner, &project_id, &s.token, &treasury);
+    assert_eq!(contract_id, predicted);
+    assert_eq!(
+        env.events().all().filter_by_contract(&s.factory_id),
+        [events::Deployed {
+            owner: owner.clone(),
+            project_id: project_id.clone(),
+            contract_id: contract_id.clone(),
+            wasm_hash: s.wasm_hash.clone(),
+            version: events::EVENT_VERSION,
+        }
+        .to_xdr(&env, &s.factory_id)]
+    );
+
+    // The instance is already initialized with the owner as its admin.
+    let instance = subscription::Client::new(&env, &contract_id);
+    assert_eq!(instance.get_admin(), owner);
+    assert_eq!(instance.get_treasury(), treasury);
+    assert_eq!(instance.get_payment_token(), s.token);
+    assert_eq!(instance.get_registry(), Some(s.factory_id.clone()));
+    assert!(instance
+        .try_init(&Address::generate(&env), &s.token, &treasury)
+        .is_err());
+
+    assert_eq!(
+        s.factory
+            .try_deploy(&owner, &project_id, &s.token, &treasury),
+        Err(Ok(FactoryError::ProjectAlreadyExists.into()))
+    );
+    let second = s.factory.deploy(
+        &owner,
+        &String::from_str(&env, "proj-2"),
+        &s.token,
+        &treasury,
+    );
+    assert_ne!(second, contract_id);
+    assert_eq!(s.factory.get_deployment_count(&owner), 2);
+    let deployments = s.factory.list_deployments(&owner, &0, &10);
+    assert_eq!(deployments.len(), 2);
+    assert_eq!(deployments.get_unchecked(0), contract_id);
+    assert_eq!(deployments.get_unchecked(1), second);
+    assert_eq!(s.factory.list_deployments(&owner, &1, &10).len(), 1);
+}
+
+#[test]
+fn project_ids_are_scoped_to_their_owner() {
+    let env = Env::default();
+    let s = setup(&env);
+    let owner = Address::generate(&env);
+    let squatter = Address::generate(&env);
+    let treasury = Address::generate(&env);
+    let project_id = String::from_str(&env, "proj-1");
+    let predicted = s.factory.predict_address(&owner, &project_id);
+
+    // Another owner deploying the same id first neither takes the address
+    // nor blocks the deployment.
+    let squatted = s
+        .factory
+        .deploy(&squatter, &project_id, &s.token, &treasury);
+    assert_ne!(squatted, predicted);
+    let contract_id = s.factory.deploy(&owner, &project_id, &s.token, &treasury);
+    assert_eq!(contract_id, predicted);
+    assert_eq!(
+        subscription::Client::new(&env, &contract_id).get_admin(),
+        owner
+    );
+
+    assert_eq!(
+        s.factory.get_project(&owner, &project_id).contract_id,
+        contract_id
+    );
+    assert_eq!(
+        s.factory.get_project(&squatter, &project_id).contract_id,
+        squatted
+    );
+    assert_eq!(s.factory.get_project_count(), 2);
+    assert_eq!(
+        s.factory
+            .try_get_project(&Address::generate(&env), &project_id),
+        Err(Ok(FactoryError::ProjectNotFound.into()))
+    );
+}
+
+#[test]
+fn registry_lists_projects_and_follows_instance_upgrades() {
+    let env = Env::default();
+    let s = setup(&env);
+    let owner = Address::generate(&env);
+    let treasury = Address::generate(&env);
+    let first_id = String::from_str(&env, "proj-1");
+    let contract_id = s.factory.deploy(&owner, &first_id, &s.token, &treasury);
+    for project in ["proj-2", "proj-3"] {
+        s.factory.deploy(
+            &Address::generate(&env),
+            &String::from_str(&env, project),
+            &s.token,
+            &treasury,
+        );
+    }
+
+    let expected = Project {
+        project_id: first_id.clone(),
+        contract_id: contract_id.clone(),
+        deployer: owner.clone(),
+        owner: owner.clone(),
+        payment_token: s.token.clone(),
+        payment_token_ledger: 0,
+        wasm_hash: s.wasm_hash.clone(),
+    };
+    assert_eq!(s.factory.get_project(&owner, &first_id), expected);
+    assert_eq!(s.factory.get_project_by_contract(&contract_id), expected);
+    assert_eq!(s.factory.get_project_count(), 3);
+    let first_page = s.factory.list_projects(&0, &2);
+    assert_eq!(first_page.len(), 2);
+    assert_eq!(first_page.get_unchecked(0), expected);
+    let second_page = s.factory.list_projects(&2, &2);
+    assert_eq!(second_page.len(), 1);
+    assert_eq!(
+        second_page.get_unchecked(0).project_id,
+        String::from_str(&env, "proj-3")
+    );
+    assert_eq!(
+        s.factory.try_list_projects(&0, &51),
+        Err(Ok(FactoryError::InvalidPageSize.into()))
+    );
+
+    // The instance reports its upgrade to the registry it was deployed by.
+    let instance = subscription::Client::new(&env, &contract_id);
+    instance.upgrade(&owner, &s.wasm_hash);
+    assert_eq!(
+        env.events().all().filter_by_contract(&s.factory_id),
+        [events::InstanceUpgraded {
+            contract_id: contract_id.clone(),
+            project_id: first_id.clone(),
+            wasm_hash: s.wasm_hash.clone(),
+            version: events::EVENT_VERSION,
+        }
+        .to_xdr(&env, &s.factory_id)]
+    );
+
+    // So do admin transfers and payment token switches.
+    let new_owner = Address::generate(&env);
+    instance.propose_admin(&owner, &new_owner);
+    instance.accept_admin();
+    let new_token = env
+        .register_stellar_asset_contract_v2(Address::generate(&env))
+        .address();
+    instance.schedule_payment_token(&new_owner, &new_token, &50);
+    assert_eq!(
+        env.events().all().filter_by_contract(&s.factory_id),
+        [events::InstancePaymentTokenChanged {
+            contract_id: contract_id.clone(),
+            project_id: first_id.clone(),
+            payment_token: new_token.clone(),
+            effective_ledger: 50,
+            version: events::EVENT_VERSION,
+        }
+        .to_xdr(&env, &s.factory_id)]
+    );
+    let project = s.factory.get_project_by_contract(&contract_id);
+    assert_eq!(project.owner, new_owner);
+    assert_eq!(project.deployer, owner);
+    assert_eq!(project.payment_token, new_token);
+    assert_eq!(project.payment_token_ledger, 50);
+    assert_eq!(
+        s.factory
+            .get_project(&project.deployer, &project.project_id),
+        project
+    );
+    assert!(s
+        .factory
+        .try_record_admin(&Address::generate(&env), &new_owner)
+        .is_err());
+
+    let new_hash = BytesN::from_array(&env, &[7; 32]);
+    s.factory.record_upgrade(&contract_id, &new_hash);
+    assert_eq!(s.factory.get_project(&owner, &first_id).wasm_hash, new_hash);
+    assert_eq!(
+        s.factory
+            .try_record_upgrade(&Address::generate(&env), &new_hash),
+        Err(Ok(FactoryError::ProjectNotFound.into()))
+    );
+}
+
+#[test]
+fn record_upgrade_must_come_from_the_instance() {
+    let env = Env::default();
+    let s = setup(&env);
+    let owner = Address::generate(&env);
+    let contract_id = s.factory.deploy(
+        &owner,
+        &String::from_str(&env, "proj-1"),
+        &s.token,
+        &Address::generate(&env),
+    );
+
+    env.set_auths(&[]);
+    assert!(s
+        .factory
+        .try_record_upgrade(&contract_id, &BytesN::from_array(&env, &[7; 32]))
+        .is_err());
+}
+
+#[test]
+fn only_admin_can_change_wasm_hash() {
+    let env = Env::default();
+    let s = setup(&env);
+
+    let new_hash = BytesN::from_array(&env, &[2; 32]);
+    assert_eq!(
+        s.factory
+            .try_set_wasm_hash(&Address::generate(&env), &new_hash),
+        Err(Ok(FactoryError::Unauthorized.into()))
+    );
+    s.factory.set_wasm_hash(&s.admin, &new_hash);
+    assert_eq!(s.factory.get_wasm_hash(), new_hash);
+}
@@ -0,0 +1,63 @@
+//! UTC calendar arithmetic on Unix timestamps, for plans billed in weeks,
+//! months or years. Day/date conversions follow Howard Hinnant's
+//! `days_from_civil` and `civil_from_days` algorithms.
+
+pub const SECONDS_PER_DAY: u64 = 86_400;
+pub const SECONDS_PER_WEEK: u64 = 7 * SECONDS_PER_DAY;
+/// A twelfth of the average Gregorian year (365.2425 days).
+pub const AVERAGE_SECONDS_PER_MONTH: u64 = 2_629_746;
+
+fn is_leap_year(year: u64) -> bool {
+    year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400))
+}
+
+fn days_in_month(year: u64, month: u64) -> u64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of the given date (month and day are 1-based).
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// `(year, month, day)` of the given number of days since 1970-01-01.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+/// The date `months` calendar months after `timestamp`, on the day of the
+/// month and at the time of day of `anchor`. When the target month is
+/// shorter, the date is clamped to its last day: with a Jan 31 anchor, the
+/// month after Jan 31 is Feb 28 (or 29) and the one after that is Mar 31.
+pub fn add_months(timestamp: u64, months: u32, anchor: u64) -> u64 {
+    let (year, month, _) = civil_from_days(timestamp / SECONDS_PER_DAY);
+    let (_, _, anchor_day) = civil_from_days(anchor / SECONDS_PER_DAY);
+    let month_index = year * 12 + (month - 1) + months as u64;
+    let (year, month) = (month_index / 12, month_index % 12 + 1);
+    let day = anchor_day.min(days_in_month(year, month));
+    days_from_civil(year, month, day) * SECONDS_PER_DAY + anchor % SECONDS_PER_DAY
+}
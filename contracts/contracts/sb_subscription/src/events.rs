@@ -0,0 +1,415 @@
+use crate::{
+    BillingPeriod, CatchUpPolicy, CouponTerms, KeeperReward, LifecycleHook, PlanDetails,
+    PriceChangePolicy, SubscriptionStatus,
+};
+use soroban_sdk::{contractevent, Address, BytesN, String, Symbol};
+
+/// Version of the event payload layout. Bumped whenever a field is added,
+/// removed or changes meaning so indexers can branch on it.
+pub const EVENT_VERSION: u32 = 1;
+
+#[contractevent(topics = ["contract", "initialized"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Initialized {
+    #[topic]
+    pub admin: Address,
+    pub payment_token: Address,
+    pub treasury: Address,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["contract", "admin_proposed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AdminProposed {
+    #[topic]
+    pub admin: Address,
+    pub new_admin: Address,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["contract", "admin_proposal_cancelled"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AdminProposalCancelled {
+    #[topic]
+    pub admin: Address,
+    pub new_admin: Address,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["contract", "admin_changed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AdminChanged {
+    #[topic]
+    pub new_admin: Address,
+    pub previous_admin: Address,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["contract", "treasury_changed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TreasuryChanged {
+    #[topic]
+    pub treasury: Address,
+    pub previous_treasury: Address,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["contract", "payment_token_change_scheduled"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PaymentTokenChangeScheduled {
+    #[topic]
+    pub token: Address,
+    pub previous_token: Address,
+    pub effective_ledger: u32,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["contract", "token_allowlist_changed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TokenAllowlistChanged {
+    #[topic]
+    pub token: Address,
+    pub allowed: bool,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["contract", "merchant_registered"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MerchantRegistered {
+    #[topic]
+    pub merchant_id: u32,
+    pub admin: Address,
+    pub treasury: Address,
+    pub payment_token: Address,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["contract", "merchant_updated"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MerchantUpdated {
+    #[topic]
+    pub merchant_id: u32,
+    pub admin: Address,
+    pub treasury: Address,
+    pub payment_token: Address,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["contract", "keeper_reward_changed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct KeeperRewardChanged {
+    #[topic]
+    pub merchant_id: u32,
+    pub reward: Option<KeeperReward>,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["contract", "lifecycle_hook_changed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LifecycleHookChanged {
+    #[topic]
+    pub merchant_id: u32,
+    pub hook: Option<LifecycleHook>,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["contract", "upgraded"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Upgraded {
+    pub new_wasm_hash: BytesN<32>,
+    pub schema_version: u32,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["contract", "registry_changed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RegistryChanged {
+    pub registry: Option<Address>,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["contract", "migrated"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Migrated {
+    pub from_version: u32,
+    pub to_version: u32,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["plan", "created"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlanCreated {
+    #[topic]
+    pub plan_id: u32,
+    pub name: String,
+    pub period_ledgers: u32,
+    pub price_stroops: i128,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["plan", "status_changed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlanStatusChanged {
+    #[topic]
+    pub plan_id: u32,
+    pub active: bool,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["plan", "period_changed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlanPeriodChanged {
+    #[topic]
+    pub plan_id: u32,
+    pub period: BillingPeriod,
+    pub period_ledgers: u32,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["plan", "catch_up_changed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlanCatchUpChanged {
+    #[topic]
+    pub plan_id: u32,
+    pub policy: CatchUpPolicy,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["plan", "pause_changed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlanPauseChanged {
+    #[topic]
+    pub plan_id: u32,
+    pub max_pauses: u32,
+    pub max_pause_ledgers: u32,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["plan", "trial_changed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlanTrialChanged {
+    #[topic]
+    pub plan_id: u32,
+    pub trial_ledgers: u32,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["plan", "dunning_changed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlanDunningChanged {
+    #[topic]
+    pub plan_id: u32,
+    pub grace_ledgers: u32,
+    pub max_failed_renewals: u32,
+    pub retry_interval_ledgers: u32,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["plan", "updated"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlanUpdated {
+    #[topic]
+    pub plan_id: u32,
+    pub details: PlanDetails,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["plan", "price_updated"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlanPriceUpdated {
+    #[topic]
+    pub plan_id: u32,
+    pub price_version: u32,
+    pub price_stroops: i128,
+    pub policy: PriceChangePolicy,
+    pub effective_ledger: u32,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["plan", "token_changed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlanTokenChanged {
+    #[topic]
+    pub plan_id: u32,
+    pub token: Option<Address>,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["coupon", "created"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CouponCreated {
+    #[topic]
+    pub code: Symbol,
+    pub merchant_id: u32,
+    pub terms: CouponTerms,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["coupon", "status_changed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CouponStatusChanged {
+    #[topic]
+    pub code: Symbol,
+    #[topic]
+    pub merchant_id: u32,
+    pub active: bool,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["coupon", "redeemed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CouponRedeemed {
+    #[topic]
+    pub code: Symbol,
+    pub subscriber: Address,
+    pub plan_id: u32,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["subscription", "subscribed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Subscribed {
+    #[topic]
+    pub subscriber: Address,
+    pub plan_id: u32,
+    pub amount: i128,
+    pub ledger: u32,
+    pub next_renewal_ledger: u32,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["subscription", "renewed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Renewed {
+    #[topic]
+    pub subscriber: Address,
+    pub plan_id: u32,
+    pub amount: i128,
+    pub ledger: u32,
+    pub next_renewal_ledger: u32,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["subscription", "keeper_rewarded"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct KeeperRewarded {
+    #[topic]
+    pub keeper: Address,
+    pub subscriber: Address,
+    pub amount: i128,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["subscription", "renewal_failed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RenewalFailed {
+    #[topic]
+    pub subscriber: Address,
+    pub plan_id: u32,
+    pub amount: i128,
+    pub ledger: u32,
+    pub failed_renewals: u32,
+    pub status: SubscriptionStatus,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["subscription", "expired"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Expired {
+    #[topic]
+    pub subscriber: Address,
+    pub plan_id: u32,
+    pub ledger: u32,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["subscription", "suspended"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Suspended {
+    #[topic]
+    pub subscriber: Address,
+    pub plan_id: u32,
+    pub ledger: u32,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["subscription", "plan_changed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlanChanged {
+    #[topic]
+    pub subscriber: Address,
+    pub from_plan_id: u32,
+    pub to_plan_id: u32,
+    pub amount: i128,
+    pub credit_stroops: i128,
+    pub ledger: u32,
+    pub next_renewal_ledger: u32,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["subscription", "plan_change_scheduled"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlanChangeScheduled {
+    #[topic]
+    pub subscriber: Address,
+    pub from_plan_id: u32,
+    pub to_plan_id: u32,
+    pub effective_ledger: u32,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["subscription", "paused"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Paused {
+    #[topic]
+    pub subscriber: Address,
+    pub plan_id: u32,
+    pub ledger: u32,
+    pub resume_ledger: Option<u32>,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["subscription", "resumed"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Resumed {
+    #[topic]
+    pub subscriber: Address,
+    pub plan_id: u32,
+    pub ledger: u32,
+    pub next_renewal_ledger: u32,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["subscription", "refunded"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Refunded {
+    #[topic]
+    pub subscriber: Address,
+    pub plan_id: u32,
+    pub token: Address,
+    pub amount: i128,
+    pub ledger: u32,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["subscription", "cancelled"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Cancelled {
+    #[topic]
+    pub subscriber: Address,
+    pub plan_id: u32,
+    pub ledger: u32,
+    pub next_renewal_ledger: u32,
+    pub version: u32,
+}
+
+#[contractevent(topics = ["subscription", "uncancelled"])]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Uncancelled {
+    #[topic]
+    pub subscriber: Address,
+    pub plan_id: u32,
+    pub ledger: u32,
+    pub next_renewal_ledger: u32,
+    pub version: u32,
+}
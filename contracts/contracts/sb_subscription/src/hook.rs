@@ -0,0 +1,60 @@
+//! Outgoing calls to the lifecycle hook a merchant registers with
+//! `set_lifecycle_hook`, so their own contracts can follow subscriptions.
+
+use crate::{
+    access_end, bump_persistent_ttl, DataKey, HookFailurePolicy, LifecycleEvent, LifecycleHook,
+    Subscription, SubscriptionError,
+};
+use soroban_sdk::{contractclient, Address, Env};
+
+/// Interface a hook contract implements. The subscription contract cannot be
+/// re-entered during the call, so the hook gets what it needs to know as
+/// arguments: `access_until` is what `access_until(subscriber)` returns right
+/// after the transition. Hooks should check that the call comes from the
+/// subscription contract they trust, e.g. with `require_auth` on its address.
+#[contractclient(name = "LifecycleHookClient")]
+pub trait LifecycleHookInterface {
+    fn on_lifecycle_event(
+        env: Env,
+        event: LifecycleEvent,
+        subscriber: Address,
+        plan_id: u32,
+        access_until: Option<u32>,
+    );
+}
+
+pub(crate) fn read_lifecycle_hook(env: &Env, merchant_id: u32) -> Option<LifecycleHook> {
+    let key = DataKey::LifecycleHook(merchant_id);
+    let hook = env.storage().persistent().get(&key)?;
+    bump_persistent_ttl(env, &key);
+    Some(hook)
+}
+
+/// Calls the hook of `merchant_id`, if one is registered, once `subscription`
+/// has gone through `event`. Only the billing events, `Subscribed` and
+/// `Renewed`, fail with `HookFailed` when a hook under `Revert` fails, and the
+/// caller then undoes the charge. Hooks can't hold up a subscription that is
+/// ending, so their failures on other events are always ignored.
+pub(crate) fn notify(
+    env: &Env,
+    merchant_id: u32,
+    subscription: &Subscription,
+    event: LifecycleEvent,
+) -> Result<(), SubscriptionError> {
+    let Some(hook) = read_lifecycle_hook(env, merchant_id) else {
+        return Ok(());
+    };
+    let result = LifecycleHookClient::new(env, &hook.contract).try_on_lifecycle_event(
+        &event,
+        &subscription.subscriber,
+        &subscription.plan_id,
+        &access_end(env, subscription),
+    );
+    if !matches!(result, Ok(Ok(())))
+        && hook.failure_policy == HookFailurePolicy::Revert
+        && matches!(event, LifecycleEvent::Subscribed | LifecycleEvent::Renewed)
+    {
+        return Err(SubscriptionError::HookFailed);
+    }
+    Ok(())
+}
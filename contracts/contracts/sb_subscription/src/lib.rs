@@ -1,9 +1,19 @@
 #![no_std]
 
+mod calendar;
+mod events;
+pub mod hook;
+mod migration;
+
 use core::cmp::min;
+use events::EVENT_VERSION;
+use migration::{
+    read_migration_cursor, read_schema_version, write_migration_cursor, write_schema_version,
+    SCHEMA_VERSION,
+};
 use soroban_sdk::{
-    contract, contracterror, contractimpl, contracttype, panic_with_error, token, Address, Env,
-    String, Vec,
+    contract, contracterror, contractimpl, contracttype, panic_with_error, token, Address, BytesN,
+    Env, String, Symbol, Val, Vec,
 };
 
 const INSTANCE_LIFETIME_THRESHOLD: u32 = 100_000;
@@ -11,6 +21,11 @@ const INSTANCE_BUMP_AMOUNT: u32 = 200_000;
 const PERSISTENT_LIFETIME_THRESHOLD: u32 = 100_000;
 const PERSISTENT_BUMP_AMOUNT: u32 = 200_000;
 const MAX_PAGE_SIZE: u32 = 50;
+const MAX_BATCH_SIZE: u32 = 50;
+/// Merchant backed by the contract-level admin, treasury and payment token.
+const DEFAULT_MERCHANT: u32 = 0;
+/// Average ledger close time, used to express time-based periods in ledgers.
+const SECONDS_PER_LEDGER: u64 = 5;
 
 #[contracterror]
 #[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
@@ -29,14 +44,42 @@ pub enum SubscriptionError {
     InvalidPrice = 11,
     RenewTooEarly = 12,
     InvalidPageSize = 13,
+    InvalidPlanChange = 14,
+    SubscriptionNotCancelled = 15,
+    SubscriptionLapsed = 16,
+    BatchTooLarge = 17,
+    InvalidKeeperReward = 18,
+    AlreadyMigrated = 19,
+    NoPendingAdmin = 20,
+    InvalidEffectiveLedger = 21,
+    TokenNotAllowed = 22,
+    MerchantAlreadyExists = 23,
+    MerchantNotFound = 24,
+    CouponAlreadyExists = 25,
+    CouponNotFound = 26,
+    CouponNotRedeemable = 27,
+    InvalidCoupon = 28,
+    PriceVersionNotFound = 29,
+    PlanFull = 30,
+    SubscriptionPaused = 31,
+    SubscriptionNotPaused = 32,
+    PauseLimitReached = 33,
+    InvalidRefund = 34,
+    HookFailed = 35,
+    MigrationOutOfOrder = 36,
 }
 
-#[contracttype]
+#[contracttype(export = false)]
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum DataKey {
     Admin,
+    PendingAdmin,
     PaymentToken,
+    PaymentTokenChange,
     Treasury,
+    KeeperReward(u32),
+    SchemaVersion,
+    Registry,
     PlanCount,
     PlanIndex(u32),
     SubscriberCount,
@@ -44,16 +87,58 @@ pub enum DataKey {
     SubscriberSeen(Address),
     Plan(u32),
     Subscription(Address),
+    TrialUsed(Address, u32),
+    AllowedToken(Address),
+    Merchant(u32),
+    MerchantPlanCount(u32),
+    MerchantPlanIndex(u32, u32),
+    Coupon(u32, Symbol),
+    PlanPrice(u32, u32),
+    PlanActiveCount(u32),
+    PaymentCount(Address),
+    Payment(Address, u32),
+    LifecycleHook(u32),
+    MigrationCursor,
 }
 
 #[contracttype]
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Plan {
     pub id: u32,
+    pub merchant_id: u32,
     pub name: String,
     pub period_ledgers: u32,
+    pub billing_period: BillingPeriod,
+    pub catch_up_policy: CatchUpPolicy,
+    pub max_pauses: u32,
+    pub max_pause_ledgers: u32,
     pub price_stroops: i128,
+    pub price_version: u32,
     pub active: bool,
+    pub trial_ledgers: u32,
+    pub grace_ledgers: u32,
+    pub max_failed_renewals: u32,
+    pub retry_interval_ledgers: u32,
+    pub payment_token: Option<Address>,
+    pub description: Option<String>,
+    pub metadata_uri: Option<String>,
+    pub metadata_hash: Option<BytesN<32>>,
+    pub display_order: u32,
+    pub max_active_subscribers: u32,
+}
+
+/// The descriptive fields of a plan, set with `update_plan`. `metadata_hash`
+/// lets clients check the document behind `metadata_uri`, and
+/// `max_active_subscribers == 0` means no limit.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlanDetails {
+    pub name: String,
+    pub description: Option<String>,
+    pub metadata_uri: Option<String>,
+    pub metadata_hash: Option<BytesN<32>>,
+    pub display_order: u32,
+    pub max_active_subscribers: u32,
 }
 
 #[contracttype]
@@ -63,9 +148,242 @@ pub struct Subscription {
     pub plan_id: u32,
     pub started_ledger: u32,
     pub next_renewal_ledger: u32,
+    pub next_renewal_timestamp: Option<u64>,
+    pub billing_anchor_timestamp: Option<u64>,
+    pub active: bool,
+    pub status: SubscriptionStatus,
+    pub failed_renewals: u32,
+    pub last_failed_ledger: u32,
+    pub last_charge_stroops: i128,
+    pub credit_stroops: i128,
+    pub pending_plan_id: Option<u32>,
+    pub cancel_at_ledger: Option<u32>,
+    pub payment_token: Address,
+    pub coupon_code: Option<Symbol>,
+    pub coupon_periods_remaining: Option<u32>,
+    pub price_version: u32,
+    pub paused_ledger: Option<u32>,
+    pub paused_timestamp: u64,
+    pub pause_count: u32,
+    pub paid_stroops: i128,
+    pub refunded_stroops: i128,
+}
+
+/// One charge collected from a subscriber and the period it paid for,
+/// estimated on time-based plans.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Payment {
+    pub plan_id: u32,
+    pub amount: i128,
+    pub token: Address,
+    pub ledger: u32,
+    pub period_start_ledger: u32,
+    pub period_end_ledger: u32,
+}
+
+/// `Active` and `PastDue` subscriptions are live and renewable, and `Paused`
+/// ones come back with `resume`; the other states are terminal until the
+/// subscriber subscribes again.
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SubscriptionStatus {
+    Active,
+    PastDue,
+    Suspended,
+    Cancelled,
+    Expired,
+    Paused,
+}
+
+/// Per-address result of `renew_many`: the status after a renewal attempt
+/// (which may itself have failed to collect payment), or the error code of a
+/// subscription that was skipped.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RenewalOutcome {
+    Processed(SubscriptionStatus),
+    Skipped(u32),
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RenewalResult {
+    pub subscriber: Address,
+    pub outcome: RenewalOutcome,
+}
+
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KeeperRewardAmount {
+    BasisPoints(u32),
+    Fixed(i128),
+}
+
+/// Where the keeper reward comes from: carved out of the renewal fee before
+/// it reaches the treasury, or paid by the treasury on top of it (which needs
+/// a treasury allowance for this contract).
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KeeperRewardSource {
+    RenewalAmount,
+    Treasury,
+}
+
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct KeeperReward {
+    pub amount: KeeperRewardAmount,
+    pub source: KeeperRewardSource,
+}
+
+/// How much `refund` pays back: a set amount, or the unused value of the
+/// current period.
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RefundAmount {
+    Fixed(i128),
+    Prorated,
+}
+
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PlanChangeTiming {
+    Immediate,
+    NextRenewal,
+}
+
+/// Length of a plan's billing period. `Ledgers` counts ledgers; the other
+/// periods are measured against the ledger timestamp, calendar ones in UTC.
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BillingPeriod {
+    Ledgers(u32),
+    Seconds(u64),
+    Weeks(u32),
+    Months(u32),
+    Years(u32),
+}
+
+/// What a renewal made more than a whole period after its due date does
+/// with the periods missed in between.
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CatchUpPolicy {
+    /// Bill each missed period, one renewal at a time.
+    ChargeAll,
+    /// Bill only the current period and skip the missed ones.
+    ChargeLatest,
+    /// End the subscription instead of billing.
+    Expire,
+}
+
+/// How subscriptions already on a plan react to a new price.
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PriceChangePolicy {
+    /// They keep paying the price version they are on.
+    Grandfathered,
+    /// They move to the new price at their first renewal at least this many
+    /// ledgers after the change.
+    NextRenewalAfter(u32),
+}
+
+/// One price of a plan. Version 1 is the price the plan was created with;
+/// `effective_ledger` is when existing subscriptions may start paying it.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlanPrice {
+    pub version: u32,
+    pub price_stroops: i128,
+    pub policy: PriceChangePolicy,
+    pub effective_ledger: u32,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CouponDiscount {
+    /// Percentage of the price, in basis points (1..=10_000).
+    BasisPoints(u32),
+    Fixed(i128),
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CouponDuration {
+    Once,
+    Periods(u32),
+    Forever,
+}
+
+/// What a coupon grants and who can redeem it. An empty `plan_ids` means any
+/// plan of the coupon's merchant; `max_redemptions == 0` means no cap.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CouponTerms {
+    pub discount: CouponDiscount,
+    pub duration: CouponDuration,
+    pub max_redemptions: u32,
+    pub expires_ledger: Option<u32>,
+    pub plan_ids: Vec<u32>,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Coupon {
+    pub code: Symbol,
+    pub merchant_id: u32,
+    pub terms: CouponTerms,
+    pub redemptions: u32,
     pub active: bool,
 }
 
+/// Subscription transitions reported to a merchant's lifecycle hook.
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LifecycleEvent {
+    Subscribed,
+    Renewed,
+    Cancelled,
+    Suspended,
+    Expired,
+}
+
+/// Whether a failing lifecycle hook undoes the charge that called it.
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HookFailurePolicy {
+    Revert,
+    Ignore,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LifecycleHook {
+    pub contract: Address,
+    pub failure_policy: HookFailurePolicy,
+}
+
+/// A tenant of a multi-merchant deployment. Merchant `DEFAULT_MERCHANT` is
+/// not stored; it mirrors the contract-level admin, treasury and token.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Merchant {
+    pub id: u32,
+    pub admin: Address,
+    pub treasury: Address,
+    pub payment_token: Address,
+}
+
+/// A payment token switch scheduled by the admin. Charges made from
+/// `effective_ledger` on use `token`; earlier ones keep the previous token.
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PaymentTokenChange {
+    pub token: Address,
+    pub effective_ledger: u32,
+}
+
 #[contract]
 pub struct SbSubscription;
 
@@ -83,14 +401,44 @@ fn read_admin(env: &Env) -> Address {
         .unwrap_or_else(|| panic_with_error!(env, SubscriptionError::NotInitialized))
 }
 
-fn read_payment_token(env: &Env) -> Address {
+fn read_pending_admin(env: &Env) -> Option<Address> {
     bump_instance_ttl(env);
+    env.storage().instance().get(&DataKey::PendingAdmin)
+}
+
+fn read_payment_token_change(env: &Env) -> Option<PaymentTokenChange> {
+    bump_instance_ttl(env);
+    env.storage().instance().get(&DataKey::PaymentTokenChange)
+}
+
+/// Returns the token new charges are made in, taking a scheduled change into
+/// account once its effective ledger is reached.
+fn read_payment_token(env: &Env) -> Address {
+    if let Some(change) = read_payment_token_change(env) {
+        if env.ledger().sequence() >= change.effective_ledger {
+            return change.token;
+        }
+    }
     env.storage()
         .instance()
         .get(&DataKey::PaymentToken)
         .unwrap_or_else(|| panic_with_error!(env, SubscriptionError::NotInitialized))
 }
 
+/// Calls `function` on the registry that lists this instance, if any, with
+/// this contract's address ahead of `args`. The registry checks that the
+/// call comes from us.
+fn report_to_registry(env: &Env, function: &str, args: &[Val]) {
+    let registry: Option<Address> = env.storage().instance().get(&DataKey::Registry);
+    if let Some(registry) = registry {
+        let mut call_args = Vec::from_array(env, [env.current_contract_address().to_val()]);
+        for arg in args {
+            call_args.push_back(*arg);
+        }
+        env.invoke_contract::<()>(&registry, &Symbol::new(env, function), call_args);
+    }
+}
+
 fn read_treasury(env: &Env) -> Address {
     bump_instance_ttl(env);
     env.storage()
@@ -99,8 +447,33 @@ fn read_treasury(env: &Env) -> Address {
         .unwrap_or_else(|| panic_with_error!(env, SubscriptionError::NotInitialized))
 }
 
-fn assert_admin(env: &Env, caller: &Address) {
-    let admin = read_admin(env);
+fn read_merchant(env: &Env, merchant_id: u32) -> Merchant {
+    if merchant_id == DEFAULT_MERCHANT {
+        return Merchant {
+            id: DEFAULT_MERCHANT,
+            admin: read_admin(env),
+            treasury: read_treasury(env),
+            payment_token: read_payment_token(env),
+        };
+    }
+    let key = DataKey::Merchant(merchant_id);
+    let merchant = env
+        .storage()
+        .persistent()
+        .get(&key)
+        .unwrap_or_else(|| panic_with_error!(env, SubscriptionError::MerchantNotFound));
+    bump_persistent_ttl(env, &key);
+    merchant
+}
+
+/// Checks `caller` against the admin of `merchant_id`; contract-wide settings
+/// belong to `DEFAULT_MERCHANT`, whose admin is the contract admin.
+fn assert_admin(env: &Env, merchant_id: u32, caller: &Address) {
+    let admin = if merchant_id == DEFAULT_MERCHANT {
+        read_admin(env)
+    } else {
+        read_merchant(env, merchant_id).admin
+    };
     if admin != *caller {
         panic_with_error!(env, SubscriptionError::Unauthorized);
     }
@@ -122,6 +495,25 @@ fn write_plan_count(env: &Env, count: u32) {
     env.storage().instance().set(&DataKey::PlanCount, &count);
 }
 
+fn read_merchant_plan_count(env: &Env, merchant_id: u32) -> u32 {
+    env.storage()
+        .persistent()
+        .get(&DataKey::MerchantPlanCount(merchant_id))
+        .unwrap_or(0)
+}
+
+fn index_merchant_plan(env: &Env, merchant_id: u32, plan_id: u32) {
+    let count_key = DataKey::MerchantPlanCount(merchant_id);
+    let plan_count = read_merchant_plan_count(env, merchant_id);
+    let index_key = DataKey::MerchantPlanIndex(merchant_id, plan_count);
+    env.storage().persistent().set(&index_key, &plan_id);
+    env.storage()
+        .persistent()
+        .set(&count_key, &(plan_count + 1));
+    bump_persistent_ttl(env, &index_key);
+    bump_persistent_ttl(env, &count_key);
+}
+
 fn read_subscriber_count(env: &Env) -> u32 {
     bump_instance_ttl(env);
     env.storage()
@@ -151,6 +543,50 @@ fn index_subscriber_if_needed(env: &Env, subscriber: &Address) {
     write_subscriber_count(env, subscriber_count + 1);
 }
 
+fn read_payment_count(env: &Env, subscriber: &Address) -> u32 {
+    env.storage()
+        .persistent()
+        .get(&DataKey::PaymentCount(subscriber.clone()))
+        .unwrap_or(0)
+}
+
+/// Records `amount`, just charged for the period from `period_start_ledger`
+/// to the subscription's next renewal: it becomes the refundable balance of
+/// that period and, unless credit covered the whole charge, is appended to
+/// the subscriber's payment history.
+fn record_payment(
+    env: &Env,
+    subscription: &mut Subscription,
+    amount: i128,
+    period_start_ledger: u32,
+) {
+    subscription.paid_stroops = amount;
+    subscription.refunded_stroops = 0;
+    if amount <= 0 {
+        return;
+    }
+    let subscriber = &subscription.subscriber;
+    let count_key = DataKey::PaymentCount(subscriber.clone());
+    let payment_count = read_payment_count(env, subscriber);
+    let payment_key = DataKey::Payment(subscriber.clone(), payment_count);
+    env.storage().persistent().set(
+        &payment_key,
+        &Payment {
+            plan_id: subscription.plan_id,
+            amount,
+            token: subscription.payment_token.clone(),
+            ledger: env.ledger().sequence(),
+            period_start_ledger,
+            period_end_ledger: subscription.next_renewal_ledger,
+        },
+    );
+    env.storage()
+        .persistent()
+        .set(&count_key, &(payment_count + 1));
+    bump_persistent_ttl(env, &payment_key);
+    bump_persistent_ttl(env, &count_key);
+}
+
 fn bump_persistent_ttl(env: &Env, key: &DataKey) {
     env.storage().persistent().extend_ttl(
         key,
@@ -159,204 +595,2179 @@ fn bump_persistent_ttl(env: &Env, key: &DataKey) {
     );
 }
 
-fn charge_subscription_fee(env: &Env, subscriber: &Address, amount: i128) {
-    let token_id = read_payment_token(env);
-    let treasury = read_treasury(env);
-    let token_client = token::TokenClient::new(env, &token_id);
-    token_client.transfer(subscriber, &treasury, &amount);
+fn set_status(subscription: &mut Subscription, status: SubscriptionStatus) {
+    subscription.status = status;
+    subscription.active = matches!(
+        status,
+        SubscriptionStatus::Active | SubscriptionStatus::PastDue
+    );
 }
 
-fn charge_subscription_fee_with_allowance(env: &Env, subscriber: &Address, amount: i128) {
-    let token_id = read_payment_token(env);
-    let treasury = read_treasury(env);
-    let token_client = token::TokenClient::new(env, &token_id);
-    let spender = env.current_contract_address();
-    token_client.transfer_from(&spender, subscriber, &treasury, &amount);
+/// Whether the period paid for has run out, i.e. the next renewal is due.
+/// Subscriptions on time-based plans are due at their renewal timestamp.
+fn period_ended(env: &Env, subscription: &Subscription) -> bool {
+    match subscription.next_renewal_timestamp {
+        Some(timestamp) => env.ledger().timestamp() >= timestamp,
+        None => env.ledger().sequence() >= subscription.next_renewal_ledger,
+    }
 }
 
-#[contractimpl]
-impl SbSubscription {
-    pub fn init(env: Env, admin: Address, payment_token: Address, treasury: Address) {
-        if env.storage().instance().has(&DataKey::Admin) {
-            panic_with_error!(&env, SubscriptionError::AlreadyInitialized);
+/// Ledgers expected to close before `timestamp`.
+fn ledgers_until(env: &Env, timestamp: u64) -> u32 {
+    let seconds = timestamp.saturating_sub(env.ledger().timestamp());
+    u32::try_from(seconds.div_ceil(SECONDS_PER_LEDGER)).unwrap_or(u32::MAX)
+}
+
+/// Ledger count standing in for `period` where ledgers are needed, such as
+/// `period_ledgers` and proration.
+fn estimated_period_ledgers(period: BillingPeriod) -> u32 {
+    let seconds = match period {
+        BillingPeriod::Ledgers(ledgers) => return ledgers,
+        BillingPeriod::Seconds(seconds) => seconds,
+        BillingPeriod::Weeks(weeks) => weeks as u64 * calendar::SECONDS_PER_WEEK,
+        BillingPeriod::Months(months) => months as u64 * calendar::AVERAGE_SECONDS_PER_MONTH,
+        BillingPeriod::Years(years) => years as u64 * 12 * calendar::AVERAGE_SECONDS_PER_MONTH,
+    };
+    u32::try_from(seconds.div_ceil(SECONDS_PER_LEDGER)).unwrap_or(u32::MAX)
+}
+
+/// `timestamp` plus one time-based `period`, with calendar periods falling
+/// on the day and time of `anchor`.
+fn add_period(timestamp: u64, period: BillingPeriod, anchor: u64) -> u64 {
+    match period {
+        BillingPeriod::Ledgers(ledgers) => {
+            timestamp.saturating_add(ledgers as u64 * SECONDS_PER_LEDGER)
+        }
+        BillingPeriod::Seconds(seconds) => timestamp.saturating_add(seconds),
+        BillingPeriod::Weeks(weeks) => {
+            timestamp.saturating_add(weeks as u64 * calendar::SECONDS_PER_WEEK)
+        }
+        BillingPeriod::Months(months) => calendar::add_months(timestamp, months, anchor),
+        BillingPeriod::Years(years) => {
+            calendar::add_months(timestamp, years.saturating_mul(12), anchor)
         }
+    }
+}
 
-        admin.require_auth();
-        env.storage().instance().set(&DataKey::Admin, &admin);
-        env.storage()
-            .instance()
-            .set(&DataKey::PaymentToken, &payment_token);
-        env.storage().instance().set(&DataKey::Treasury, &treasury);
-        bump_instance_ttl(&env);
+/// Moves the subscription's due date one period of `plan` past the current
+/// one, so renewals stay on schedule however late they are made. On
+/// time-based plans `next_renewal_ledger` is re-estimated from the new due
+/// timestamp.
+fn advance_renewal(env: &Env, subscription: &mut Subscription, plan: &Plan) {
+    if let BillingPeriod::Ledgers(_) = plan.billing_period {
+        subscription.next_renewal_ledger = subscription
+            .next_renewal_ledger
+            .saturating_add(plan.period_ledgers);
+        subscription.next_renewal_timestamp = None;
+        subscription.billing_anchor_timestamp = None;
+        return;
     }
+    let due = subscription
+        .next_renewal_timestamp
+        .unwrap_or_else(|| env.ledger().timestamp());
+    let anchor = *subscription.billing_anchor_timestamp.get_or_insert(due);
+    let next_due = add_period(due, plan.billing_period, anchor);
+    subscription.next_renewal_timestamp = Some(next_due);
+    subscription.next_renewal_ledger = env.ledger().sequence() + ledgers_until(env, next_due);
+}
 
-    pub fn create_plan(
-        env: Env,
-        caller: Address,
-        plan_id: u32,
-        name: String,
-        period_ledgers: u32,
-        price_stroops: i128,
-    ) {
-        caller.require_auth();
-        assert_admin(&env, &caller);
+/// Starts a billing schedule on `plan` with a period beginning now.
+fn start_renewal_schedule(env: &Env, subscription: &mut Subscription, plan: &Plan) {
+    subscription.next_renewal_ledger = env.ledger().sequence();
+    subscription.next_renewal_timestamp = Some(env.ledger().timestamp());
+    subscription.billing_anchor_timestamp = None;
+    advance_renewal(env, subscription, plan);
+}
 
-        if period_ledgers == 0 {
-            panic_with_error!(&env, SubscriptionError::InvalidPeriod);
+/// Whether a whole period of `plan` went by after the due date without a
+/// renewal.
+fn missed_period(env: &Env, subscription: &Subscription, plan: &Plan) -> bool {
+    let mut next = subscription.clone();
+    advance_renewal(env, &mut next, plan);
+    period_ended(env, &next)
+}
+
+/// Moves the due date forward to the start of the current period, dropping
+/// the periods missed before it. On time-based plans `next_renewal_ledger`
+/// is re-estimated from the new due timestamp.
+fn skip_missed_periods(env: &Env, subscription: &mut Subscription, plan: &Plan) {
+    let period_seconds = match plan.billing_period {
+        BillingPeriod::Ledgers(_) => {
+            let late = env
+                .ledger()
+                .sequence()
+                .saturating_sub(subscription.next_renewal_ledger);
+            subscription.next_renewal_ledger += late - late % plan.period_ledgers;
+            return;
         }
-        if price_stroops <= 0 {
-            panic_with_error!(&env, SubscriptionError::InvalidPrice);
+        BillingPeriod::Seconds(seconds) => Some(seconds),
+        BillingPeriod::Weeks(weeks) => Some(weeks as u64 * calendar::SECONDS_PER_WEEK),
+        BillingPeriod::Months(_) | BillingPeriod::Years(_) => None,
+    };
+    let now = env.ledger().timestamp();
+    let mut due = subscription.next_renewal_timestamp.unwrap_or(now);
+    match period_seconds {
+        Some(length) => due += now.saturating_sub(due) / length * length,
+        None => {
+            let anchor = *subscription.billing_anchor_timestamp.get_or_insert(due);
+            loop {
+                let next_due = add_period(due, plan.billing_period, anchor);
+                if next_due > now {
+                    break;
+                }
+                due = next_due;
+            }
         }
+    }
+    subscription.next_renewal_timestamp = Some(due);
+    let late_ledgers =
+        u32::try_from(now.saturating_sub(due) / SECONDS_PER_LEDGER).unwrap_or(u32::MAX);
+    subscription.next_renewal_ledger = env.ledger().sequence().saturating_sub(late_ledgers);
+}
 
-        let key = DataKey::Plan(plan_id);
-        if env.storage().persistent().has(&key) {
-            panic_with_error!(&env, SubscriptionError::PlanAlreadyExists);
+/// Applies transitions that only depend on the current ledger (a scheduled
+/// cancellation being reached, a grace period running out), so reads see
+/// them without anyone having to touch the subscription on-chain.
+fn settle_subscription(env: &Env, subscription: &mut Subscription) {
+    let current_ledger = env.ledger().sequence();
+    if subscription.active
+        && subscription.cancel_at_ledger.is_some()
+        && period_ended(env, subscription)
+    {
+        set_status(subscription, SubscriptionStatus::Cancelled);
+    }
+    if subscription.paused_ledger.is_none() && subscription.status != SubscriptionStatus::PastDue {
+        return;
+    }
+    let (max_pause_ledgers, grace_ledgers) = env
+        .storage()
+        .persistent()
+        .get::<_, Plan>(&DataKey::Plan(subscription.plan_id))
+        .map(|plan| (plan.max_pause_ledgers, plan.grace_ledgers))
+        .unwrap_or((0, 0));
+    if let Some(paused_ledger) = subscription.paused_ledger {
+        // Pauses longer than the plan allows end on their own.
+        if max_pause_ledgers > 0
+            && current_ledger >= paused_ledger.saturating_add(max_pause_ledgers)
+        {
+            resume_subscription(
+                subscription,
+                max_pause_ledgers,
+                max_pause_ledgers as u64 * SECONDS_PER_LEDGER,
+            );
         }
+    }
+    if subscription.status == SubscriptionStatus::PastDue
+        && grace_ledgers > 0
+        && current_ledger
+            >= subscription
+                .next_renewal_ledger
+                .saturating_add(grace_ledgers)
+    {
+        set_status(subscription, SubscriptionStatus::Suspended);
+    }
+}
 
-        let plan = Plan {
-            id: plan_id,
-            name,
-            period_ledgers,
-            price_stroops,
-            active: true,
-        };
-        env.storage().persistent().set(&key, &plan);
-        let plan_count = read_plan_count(&env);
-        let index_key = DataKey::PlanIndex(plan_count);
-        env.storage().persistent().set(&index_key, &plan_id);
-        bump_persistent_ttl(&env, &index_key);
-        write_plan_count(&env, plan_count + 1);
-        bump_persistent_ttl(&env, &key);
+/// Settles a subscription that is about to be written back. When settling
+/// ends it, e.g. because its grace period ran out, its seat is freed and
+/// `true` is returned.
+fn settle_and_free_seat(env: &Env, subscription: &mut Subscription) -> bool {
+    let held_seat = holds_seat(subscription).then(|| seat_plan_id(subscription));
+    settle_subscription(env, subscription);
+    let freed = held_seat.is_some() && !holds_seat(subscription);
+    if let Some(plan_id) = held_seat.filter(|_| freed) {
+        release_seat(env, plan_id);
     }
+    freed
+}
 
-    pub fn set_plan_status(env: Env, caller: Address, plan_id: u32, active: bool) {
-        caller.require_auth();
-        assert_admin(&env, &caller);
+/// Ends a pause that lasted `paused_ledgers` ledgers (`paused_seconds` on
+/// time-based plans). The paid time left when the subscription was paused
+/// runs again from now on, so its due date moves back by the pause length.
+fn resume_subscription(subscription: &mut Subscription, paused_ledgers: u32, paused_seconds: u64) {
+    subscription.paused_ledger = None;
+    subscription.next_renewal_ledger = subscription
+        .next_renewal_ledger
+        .saturating_add(paused_ledgers);
+    if let Some(timestamp) = subscription.next_renewal_timestamp {
+        subscription.next_renewal_timestamp = Some(timestamp.saturating_add(paused_seconds));
+        subscription.billing_anchor_timestamp = None;
+    }
+    set_status(subscription, SubscriptionStatus::Active);
+}
 
-        let key = DataKey::Plan(plan_id);
-        let mut plan: Plan = env
-            .storage()
-            .persistent()
-            .get(&key)
-            .unwrap_or_else(|| panic_with_error!(&env, SubscriptionError::PlanNotFound));
+/// Whether a stored subscription counts towards its plan's
+/// `max_active_subscribers`. The seat is on the plan it is moving to when a
+/// plan change is scheduled. Cancelling, expiring or being suspended frees
+/// it; pausing doesn't.
+fn holds_seat(subscription: &Subscription) -> bool {
+    (subscription.active || subscription.status == SubscriptionStatus::Paused)
+        && subscription.cancel_at_ledger.is_none()
+}
 
-        plan.active = active;
-        env.storage().persistent().set(&key, &plan);
-        bump_persistent_ttl(&env, &key);
+fn seat_plan_id(subscription: &Subscription) -> u32 {
+    subscription.pending_plan_id.unwrap_or(subscription.plan_id)
+}
+
+fn read_active_count(env: &Env, plan_id: u32) -> u32 {
+    let key = DataKey::PlanActiveCount(plan_id);
+    let count = env.storage().persistent().get(&key).unwrap_or(0);
+    if count > 0 {
+        bump_persistent_ttl(env, &key);
     }
+    count
+}
 
-    pub fn subscribe(env: Env, subscriber: Address, plan_id: u32) {
-        subscriber.require_auth();
+fn write_active_count(env: &Env, plan_id: u32, count: u32) {
+    let key = DataKey::PlanActiveCount(plan_id);
+    env.storage().persistent().set(&key, &count);
+    bump_persistent_ttl(env, &key);
+}
 
-        let plan_key = DataKey::Plan(plan_id);
-        let plan: Plan = env
-            .storage()
-            .persistent()
-            .get(&plan_key)
-            .unwrap_or_else(|| panic_with_error!(&env, SubscriptionError::PlanNotFound));
+fn take_seat(env: &Env, plan: &Plan) -> Result<(), SubscriptionError> {
+    let count = read_active_count(env, plan.id);
+    if plan.max_active_subscribers > 0 && count >= plan.max_active_subscribers {
+        return Err(SubscriptionError::PlanFull);
+    }
+    write_active_count(env, plan.id, count + 1);
+    Ok(())
+}
 
-        if !plan.active {
-            panic_with_error!(&env, SubscriptionError::PlanInactive);
-        }
+fn release_seat(env: &Env, plan_id: u32) {
+    // Subscriptions from before seats were counted may release one that was
+    // never taken.
+    let count = read_active_count(env, plan_id);
+    write_active_count(env, plan_id, count.saturating_sub(1));
+}
 
-        charge_subscription_fee(&env, &subscriber, plan.price_stroops);
+fn read_plan(env: &Env, plan_id: u32) -> Plan {
+    let key = DataKey::Plan(plan_id);
+    let plan: Plan = env
+        .storage()
+        .persistent()
+        .get(&key)
+        .unwrap_or_else(|| panic_with_error!(env, SubscriptionError::PlanNotFound));
+    bump_persistent_ttl(env, &key);
+    plan
+}
 
-        let subscription_key = DataKey::Subscription(subscriber.clone());
-        if env.storage().persistent().has(&subscription_key) {
-            let existing: Subscription = env
-                .storage()
-                .persistent()
-                .get(&subscription_key)
-                .unwrap_or_else(|| panic_with_error!(&env, SubscriptionError::SubscriptionNotFound));
-            if existing.active {
-                panic_with_error!(&env, SubscriptionError::SubscriptionAlreadyExists);
-            }
-        }
+/// Reads the subscription of `subscriber`, settled to the current ledger.
+fn try_read_subscription(env: &Env, subscriber: &Address) -> Option<Subscription> {
+    let mut subscription: Subscription = env
+        .storage()
+        .persistent()
+        .get(&DataKey::Subscription(subscriber.clone()))?;
+    settle_subscription(env, &mut subscription);
+    Some(subscription)
+}
 
-        let current_ledger = env.ledger().sequence();
-        let subscription = Subscription {
-            subscriber: subscriber.clone(),
-            plan_id,
-            started_ledger: current_ledger,
-            next_renewal_ledger: current_ledger + plan.period_ledgers,
-            active: true,
-        };
+fn read_subscription(env: &Env, subscriber: &Address) -> Subscription {
+    try_read_subscription(env, subscriber)
+        .unwrap_or_else(|| panic_with_error!(env, SubscriptionError::SubscriptionNotFound))
+}
 
-        env.storage().persistent().set(&subscription_key, &subscription);
-        index_subscriber_if_needed(&env, &subscriber);
-        bump_persistent_ttl(&env, &plan_key);
-        bump_persistent_ttl(&env, &subscription_key);
+/// Ledger at which a settled subscription stops granting access, or `None`
+/// if it grants none. A renewal that is due but unpaid keeps access through
+/// the plan's grace period, unless the subscription is cancelled or its plan
+/// retired, in which case access ends with the paid period.
+fn access_end(env: &Env, subscription: &Subscription) -> Option<u32> {
+    if !subscription.active {
+        return None;
     }
+    let grace_ledgers = match env
+        .storage()
+        .persistent()
+        .get::<_, Plan>(&DataKey::Plan(subscription.plan_id))
+    {
+        Some(plan) if plan.active && subscription.cancel_at_ledger.is_none() => plan.grace_ledgers,
+        _ => 0,
+    };
+    Some(
+        subscription
+            .next_renewal_ledger
+            .saturating_add(grace_ledgers),
+    )
+    .filter(|end| env.ledger().sequence() < *end)
+}
 
-    pub fn renew(env: Env, subscriber: Address) {
-        let subscription_key = DataKey::Subscription(subscriber.clone());
-        let mut subscription: Subscription = env
-            .storage()
-            .persistent()
-            .get(&subscription_key)
-            .unwrap_or_else(|| panic_with_error!(&env, SubscriptionError::SubscriptionNotFound));
+fn write_subscription(env: &Env, subscription: &Subscription) {
+    let key = DataKey::Subscription(subscription.subscriber.clone());
+    env.storage().persistent().set(&key, subscription);
+    bump_persistent_ttl(env, &key);
+}
 
-        if !subscription.active {
-            panic_with_error!(&env, SubscriptionError::SubscriptionCancelled);
-        }
+/// Reads a plan that `caller`, who must be its merchant admin, is changing.
+fn read_admin_plan(env: &Env, caller: &Address, plan_id: u32) -> Plan {
+    caller.require_auth();
+    let plan = read_plan(env, plan_id);
+    assert_admin(env, plan.merchant_id, caller);
+    plan
+}
 
-        let plan_key = DataKey::Plan(subscription.plan_id);
-        let plan: Plan = env
-            .storage()
-            .persistent()
-            .get(&plan_key)
-            .unwrap_or_else(|| panic_with_error!(&env, SubscriptionError::PlanNotFound));
-        if !plan.active {
-            panic_with_error!(&env, SubscriptionError::PlanInactive);
-        }
+fn write_plan(env: &Env, plan: &Plan) {
+    let key = DataKey::Plan(plan.id);
+    env.storage().persistent().set(&key, plan);
+    bump_persistent_ttl(env, &key);
+}
 
-        let current_ledger = env.ledger().sequence();
-        if current_ledger < subscription.next_renewal_ledger {
-            panic_with_error!(&env, SubscriptionError::RenewTooEarly);
+/// Share of `value` matching the part of the current period that has not
+/// been consumed yet.
+fn unused_period_value(env: &Env, subscription: &Subscription, plan: &Plan, value: i128) -> i128 {
+    let remaining = match subscription.next_renewal_timestamp {
+        Some(timestamp) => ledgers_until(env, timestamp),
+        None => subscription
+            .next_renewal_ledger
+            .saturating_sub(env.ledger().sequence()),
+    };
+    let remaining = min(remaining, plan.period_ledgers);
+    value * remaining as i128 / plan.period_ledgers as i128
+}
+
+fn read_price_version(env: &Env, plan_id: u32, version: u32) -> Option<PlanPrice> {
+    let key = DataKey::PlanPrice(plan_id, version);
+    let price = env.storage().persistent().get(&key)?;
+    bump_persistent_ttl(env, &key);
+    Some(price)
+}
+
+fn write_price_version(env: &Env, plan_id: u32, price: &PlanPrice) {
+    let key = DataKey::PlanPrice(plan_id, price.version);
+    env.storage().persistent().set(&key, price);
+    bump_persistent_ttl(env, &key);
+}
+
+/// Price version and price billed for a period of `plan` charged now. A
+/// subscription joining the plan pays its current price. One already on it
+/// keeps its version until a later version that is not grandfathered has
+/// taken effect, and then moves to the latest such version.
+fn billed_price(env: &Env, subscription: &Subscription, plan: &Plan) -> (u32, i128) {
+    if subscription.plan_id != plan.id || subscription.price_version >= plan.price_version {
+        return (plan.price_version, plan.price_stroops);
+    }
+    let current_ledger = env.ledger().sequence();
+    let mut version = plan.price_version;
+    loop {
+        let price = read_price_version(env, plan.id, version)
+            .unwrap_or_else(|| panic_with_error!(env, SubscriptionError::PriceVersionNotFound));
+        let moves = match price.policy {
+            PriceChangePolicy::Grandfathered => false,
+            PriceChangePolicy::NextRenewalAfter(_) => current_ledger >= price.effective_ledger,
+        };
+        if moves || version == subscription.price_version {
+            return (version, price.price_stroops);
+        }
+        version -= 1;
+    }
+}
+
+fn is_token_allowed(env: &Env, token: &Address) -> bool {
+    let key = DataKey::AllowedToken(token.clone());
+    let allowed = env.storage().persistent().has(&key);
+    if allowed {
+        bump_persistent_ttl(env, &key);
+    }
+    allowed
+}
+
+/// Plans without a token of their own follow their merchant's payment token,
+/// including scheduled rotations of the contract token.
+fn plan_payment_token(env: &Env, plan: &Plan) -> Address {
+    plan.payment_token
+        .clone()
+        .unwrap_or_else(|| read_merchant(env, plan.merchant_id).payment_token)
+}
+
+fn read_coupon(env: &Env, merchant_id: u32, code: &Symbol) -> Option<Coupon> {
+    let key = DataKey::Coupon(merchant_id, code.clone());
+    let coupon = env.storage().persistent().get(&key);
+    if coupon.is_some() {
+        bump_persistent_ttl(env, &key);
+    }
+    coupon
+}
+
+/// Returns the subscription's coupon if it covers charges for `plan`.
+/// Coupon terms never change after creation, so reading them at charge time
+/// gives the subscriber what they redeemed.
+fn covering_coupon(env: &Env, subscription: &Subscription, plan: &Plan) -> Option<Coupon> {
+    let code = subscription.coupon_code.as_ref()?;
+    let coupon = read_coupon(env, plan.merchant_id, code)?;
+    let plan_ids = &coupon.terms.plan_ids;
+    (plan_ids.is_empty() || plan_ids.contains(plan.id)).then_some(coupon)
+}
+
+/// Returns the price of a period of `plan` after the subscription's coupon,
+/// if it has one that covers the plan.
+fn discounted_price(env: &Env, subscription: &Subscription, plan: &Plan, price: i128) -> i128 {
+    match covering_coupon(env, subscription, plan) {
+        Some(coupon) => match coupon.terms.discount {
+            CouponDiscount::BasisPoints(bps) => price - price * bps as i128 / 10_000,
+            CouponDiscount::Fixed(fixed) => (price - fixed).max(0),
+        },
+        None => price,
+    }
+}
+
+/// Counts a billed period of `plan` against the subscription's coupon and
+/// drops the coupon once it has run out.
+fn use_coupon_period(env: &Env, subscription: &mut Subscription, plan: &Plan) {
+    if covering_coupon(env, subscription, plan).is_none() {
+        return;
+    }
+    match subscription.coupon_periods_remaining {
+        Some(periods) if periods <= 1 => {
+            subscription.coupon_code = None;
+            subscription.coupon_periods_remaining = None;
+        }
+        Some(periods) => subscription.coupon_periods_remaining = Some(periods - 1),
+        None => {}
+    }
+}
+
+/// Checks that `code` can be redeemed for `plan` and counts the redemption.
+/// Returns the number of periods the discount lasts, `None` for forever.
+fn redeem_coupon(env: &Env, code: &Symbol, plan: &Plan) -> Result<Option<u32>, SubscriptionError> {
+    let mut coupon =
+        read_coupon(env, plan.merchant_id, code).ok_or(SubscriptionError::CouponNotFound)?;
+    let terms = &coupon.terms;
+    let expired = terms
+        .expires_ledger
+        .is_some_and(|expires| env.ledger().sequence() > expires);
+    let exhausted = terms.max_redemptions > 0 && coupon.redemptions >= terms.max_redemptions;
+    let applicable = terms.plan_ids.is_empty() || terms.plan_ids.contains(plan.id);
+    if !coupon.active || expired || exhausted || !applicable {
+        return Err(SubscriptionError::CouponNotRedeemable);
+    }
+
+    let periods = match terms.duration {
+        CouponDuration::Once => Some(1),
+        CouponDuration::Periods(periods) => Some(periods),
+        CouponDuration::Forever => None,
+    };
+    coupon.redemptions += 1;
+    env.storage()
+        .persistent()
+        .set(&DataKey::Coupon(plan.merchant_id, code.clone()), &coupon);
+    Ok(periods)
+}
+
+/// Consumes as much of the subscription credit as possible against `price`
+/// and returns the amount left to charge.
+fn apply_credit(subscription: &mut Subscription, price: i128) -> i128 {
+    let covered = min(subscription.credit_stroops, price);
+    subscription.credit_stroops -= covered;
+    price - covered
+}
+
+fn charge_subscription_fee(
+    env: &Env,
+    token_id: &Address,
+    treasury: &Address,
+    subscriber: &Address,
+    amount: i128,
+) {
+    let token_client = token::TokenClient::new(env, token_id);
+    token_client.transfer(subscriber, treasury, &amount);
+}
+
+fn read_keeper_reward(env: &Env, merchant_id: u32) -> Option<KeeperReward> {
+    let key = DataKey::KeeperReward(merchant_id);
+    let reward = env.storage().persistent().get(&key)?;
+    bump_persistent_ttl(env, &key);
+    Some(reward)
+}
+
+fn keeper_reward_for(reward: &KeeperReward, amount: i128) -> i128 {
+    let value = match reward.amount {
+        KeeperRewardAmount::BasisPoints(bps) => amount * bps as i128 / 10_000,
+        KeeperRewardAmount::Fixed(fixed) => fixed,
+    };
+    match reward.source {
+        KeeperRewardSource::RenewalAmount => min(value, amount),
+        KeeperRewardSource::Treasury => value,
+    }
+}
+
+/// Pulls a renewal fee from the subscriber's allowance into this contract,
+/// where it waits until the renewal is settled. Returns `false` instead of
+/// trapping when the fee cannot be collected, so the failed attempt can be
+/// recorded on the subscription.
+fn try_collect_renewal_fee(
+    env: &Env,
+    token_id: &Address,
+    subscriber: &Address,
+    amount: i128,
+) -> bool {
+    let spender = env.current_contract_address();
+    matches!(
+        token::TokenClient::new(env, token_id)
+            .try_transfer_from(&spender, subscriber, &spender, &amount),
+        Ok(Ok(()))
+    )
+}
+
+/// Pays a fee collected by `try_collect_renewal_fee` to the treasury and the
+/// keeper reward, if the merchant configured one, to `keeper`. Returns the
+/// reward paid.
+fn pay_out_renewal_fee(
+    env: &Env,
+    merchant_id: u32,
+    token_id: &Address,
+    treasury: &Address,
+    keeper: &Address,
+    amount: i128,
+) -> i128 {
+    let token_client = token::TokenClient::new(env, token_id);
+    let spender = env.current_contract_address();
+    let reward = read_keeper_reward(env, merchant_id);
+    let reward_amount = reward.map_or(0, |reward| keeper_reward_for(&reward, amount));
+
+    match reward {
+        Some(KeeperReward {
+            source: KeeperRewardSource::RenewalAmount,
+            ..
+        }) if reward_amount > 0 => {
+            if amount > reward_amount {
+                token_client.transfer(&spender, treasury, &(amount - reward_amount));
+            }
+            token_client.transfer(&spender, keeper, &reward_amount);
+            reward_amount
+        }
+        _ => {
+            token_client.transfer(&spender, treasury, &amount);
+            // A treasury that has not approved enough allowance simply
+            // stops paying rewards; it never blocks the renewal itself.
+            if reward_amount > 0
+                && matches!(
+                    token_client.try_transfer_from(&spender, treasury, keeper, &reward_amount),
+                    Ok(Ok(()))
+                )
+            {
+                return reward_amount;
+            }
+            0
+        }
+    }
+}
+
+fn insert_plan(
+    env: &Env,
+    caller: &Address,
+    merchant_id: u32,
+    plan_id: u32,
+    name: String,
+    period_ledgers: u32,
+    price_stroops: i128,
+) {
+    caller.require_auth();
+    assert_admin(env, merchant_id, caller);
+
+    if period_ledgers == 0 {
+        panic_with_error!(env, SubscriptionError::InvalidPeriod);
+    }
+    if price_stroops <= 0 {
+        panic_with_error!(env, SubscriptionError::InvalidPrice);
+    }
+
+    let key = DataKey::Plan(plan_id);
+    if env.storage().persistent().has(&key) {
+        panic_with_error!(env, SubscriptionError::PlanAlreadyExists);
+    }
+
+    let plan = Plan {
+        id: plan_id,
+        merchant_id,
+        name,
+        period_ledgers,
+        billing_period: BillingPeriod::Ledgers(period_ledgers),
+        catch_up_policy: CatchUpPolicy::ChargeLatest,
+        max_pauses: 0,
+        max_pause_ledgers: 0,
+        price_stroops,
+        price_version: 1,
+        active: true,
+        trial_ledgers: 0,
+        grace_ledgers: 0,
+        max_failed_renewals: 0,
+        retry_interval_ledgers: 0,
+        payment_token: None,
+        description: None,
+        metadata_uri: None,
+        metadata_hash: None,
+        display_order: 0,
+        max_active_subscribers: 0,
+    };
+    env.storage().persistent().set(&key, &plan);
+    write_price_version(
+        env,
+        plan_id,
+        &PlanPrice {
+            version: 1,
+            price_stroops,
+            policy: PriceChangePolicy::Grandfathered,
+            effective_ledger: env.ledger().sequence(),
+        },
+    );
+    let plan_count = read_plan_count(env);
+    let index_key = DataKey::PlanIndex(plan_count);
+    env.storage().persistent().set(&index_key, &plan_id);
+    bump_persistent_ttl(env, &index_key);
+    write_plan_count(env, plan_count + 1);
+    index_merchant_plan(env, merchant_id, plan_id);
+    bump_persistent_ttl(env, &key);
+
+    events::PlanCreated {
+        plan_id,
+        name: plan.name,
+        period_ledgers,
+        price_stroops,
+        version: EVENT_VERSION,
+    }
+    .publish(env);
+}
+
+/// Charges a due subscription from the subscriber's allowance. A failed
+/// charge is recorded rather than reverted: the subscription moves to
+/// `PastDue` or `Suspended` per the plan's dunning settings. Subscriptions
+/// that cannot be renewed right now are reported as an error.
+fn renew_subscription(
+    env: &Env,
+    keeper: &Address,
+    subscriber: Address,
+) -> Result<SubscriptionStatus, SubscriptionError> {
+    let subscription_key = DataKey::Subscription(subscriber.clone());
+    let mut subscription: Subscription = env
+        .storage()
+        .persistent()
+        .get(&subscription_key)
+        .ok_or(SubscriptionError::SubscriptionNotFound)?;
+
+    // A subscription whose grace period ran out is stored as suspended here,
+    // so the seat it held can go to someone else.
+    if settle_and_free_seat(env, &mut subscription) {
+        write_subscription(env, &subscription);
+
+        events::Suspended {
+            subscriber,
+            plan_id: subscription.plan_id,
+            ledger: env.ledger().sequence(),
+            version: EVENT_VERSION,
+        }
+        .publish(env);
+        let _ = hook::notify(
+            env,
+            read_plan(env, subscription.plan_id).merchant_id,
+            &subscription,
+            LifecycleEvent::Suspended,
+        );
+        return Ok(subscription.status);
+    }
+    match subscription.status {
+        SubscriptionStatus::Active | SubscriptionStatus::PastDue => {}
+        SubscriptionStatus::Cancelled => return Err(SubscriptionError::SubscriptionCancelled),
+        SubscriptionStatus::Suspended | SubscriptionStatus::Expired => {
+            return Err(SubscriptionError::SubscriptionLapsed)
+        }
+        SubscriptionStatus::Paused => return Err(SubscriptionError::SubscriptionPaused),
+    }
+    if subscription.cancel_at_ledger.is_some() {
+        return Err(SubscriptionError::SubscriptionCancelled);
+    }
+
+    // A downgrade or upgrade scheduled with `PlanChangeTiming::NextRenewal`
+    // takes effect here, and this renewal is billed at the new plan price.
+    let plan_id = subscription.pending_plan_id.unwrap_or(subscription.plan_id);
+    let plan_key = DataKey::Plan(plan_id);
+    let plan: Plan = env
+        .storage()
+        .persistent()
+        .get(&plan_key)
+        .ok_or(SubscriptionError::PlanNotFound)?;
+
+    let current_ledger = env.ledger().sequence();
+    if !period_ended(env, &subscription) {
+        return Err(SubscriptionError::RenewTooEarly);
+    }
+    if subscription.status == SubscriptionStatus::PastDue
+        && current_ledger
+            < subscription
+                .last_failed_ledger
+                .saturating_add(plan.retry_interval_ledgers)
+    {
+        return Err(SubscriptionError::RenewTooEarly);
+    }
+
+    // A retired plan is not billed again; its subscriptions run out at the
+    // end of the period they paid for. Subscriptions left unrenewed for a
+    // whole period may run out too, depending on the plan.
+    let missed = missed_period(env, &subscription, &plan);
+    if !plan.active || (missed && plan.catch_up_policy == CatchUpPolicy::Expire) {
+        set_status(&mut subscription, SubscriptionStatus::Expired);
+        release_seat(env, plan_id);
+        env.storage()
+            .persistent()
+            .set(&subscription_key, &subscription);
+        bump_persistent_ttl(env, &subscription_key);
+
+        events::Expired {
+            subscriber,
+            plan_id,
+            ledger: current_ledger,
+            version: EVENT_VERSION,
+        }
+        .publish(env);
+        let _ = hook::notify(
+            env,
+            plan.merchant_id,
+            &subscription,
+            LifecycleEvent::Expired,
+        );
+        return Ok(subscription.status);
+    }
+
+    if missed && plan.catch_up_policy == CatchUpPolicy::ChargeLatest {
+        skip_missed_periods(env, &mut subscription, &plan);
+    }
+
+    let period_start_ledger = subscription.next_renewal_ledger;
+    // Renewals are where subscriptions move over to a rotated payment token.
+    let token_id = plan_payment_token(env, &plan);
+    let treasury = read_merchant(env, plan.merchant_id).treasury;
+    let credit_before = subscription.credit_stroops;
+    // Credit is kept in the token it was paid in, so a renewal that moves to
+    // another token gives it up.
+    if token_id != subscription.payment_token {
+        subscription.credit_stroops = 0;
+    }
+    let (price_version, full_price) = billed_price(env, &subscription, &plan);
+    let price = discounted_price(env, &subscription, &plan, full_price);
+    let amount = apply_credit(&mut subscription, price);
+    if amount > 0 && !try_collect_renewal_fee(env, &token_id, &subscriber, amount) {
+        subscription.credit_stroops = credit_before;
+        subscription.failed_renewals += 1;
+        subscription.last_failed_ledger = current_ledger;
+        let attempts_exhausted = plan.max_failed_renewals > 0
+            && subscription.failed_renewals >= plan.max_failed_renewals;
+        set_status(
+            &mut subscription,
+            if attempts_exhausted {
+                SubscriptionStatus::Suspended
+            } else {
+                SubscriptionStatus::PastDue
+            },
+        );
+        settle_subscription(env, &mut subscription);
+        if !subscription.active {
+            release_seat(env, plan_id);
+        }
+        env.storage()
+            .persistent()
+            .set(&subscription_key, &subscription);
+        bump_persistent_ttl(env, &subscription_key);
+
+        events::RenewalFailed {
+            subscriber,
+            plan_id: subscription.plan_id,
+            amount,
+            ledger: current_ledger,
+            failed_renewals: subscription.failed_renewals,
+            status: subscription.status,
+            version: EVENT_VERSION,
+        }
+        .publish(env);
+        if subscription.status == SubscriptionStatus::Suspended {
+            let _ = hook::notify(
+                env,
+                plan.merchant_id,
+                &subscription,
+                LifecycleEvent::Suspended,
+            );
+        }
+        return Ok(subscription.status);
+    }
+
+    subscription.plan_id = plan_id;
+    subscription.pending_plan_id = None;
+    subscription.last_charge_stroops = price;
+    subscription.price_version = price_version;
+    subscription.payment_token = token_id;
+    use_coupon_period(env, &mut subscription, &plan);
+    advance_renewal(env, &mut subscription, &plan);
+    subscription.failed_renewals = 0;
+    set_status(&mut subscription, SubscriptionStatus::Active);
+    // Nothing is stored before the merchant hook accepts the renewal. If a
+    // hook under `Revert` fails, the fee goes back and the renewal is skipped.
+    if let Err(err) = hook::notify(
+        env,
+        plan.merchant_id,
+        &subscription,
+        LifecycleEvent::Renewed,
+    ) {
+        if amount > 0 {
+            token::TokenClient::new(env, &subscription.payment_token).transfer(
+                &env.current_contract_address(),
+                &subscriber,
+                &amount,
+            );
+        }
+        return Err(err);
+    }
+    let keeper_reward = if amount > 0 {
+        pay_out_renewal_fee(
+            env,
+            plan.merchant_id,
+            &subscription.payment_token,
+            &treasury,
+            keeper,
+            amount,
+        )
+    } else {
+        0
+    };
+    record_payment(env, &mut subscription, amount, period_start_ledger);
+    env.storage()
+        .persistent()
+        .set(&subscription_key, &subscription);
+    bump_persistent_ttl(env, &plan_key);
+    bump_persistent_ttl(env, &subscription_key);
+
+    if keeper_reward > 0 {
+        events::KeeperRewarded {
+            keeper: keeper.clone(),
+            subscriber: subscriber.clone(),
+            amount: keeper_reward,
+            version: EVENT_VERSION,
+        }
+        .publish(env);
+    }
+    events::Renewed {
+        subscriber,
+        plan_id,
+        amount,
+        ledger: current_ledger,
+        next_renewal_ledger: subscription.next_renewal_ledger,
+        version: EVENT_VERSION,
+    }
+    .publish(env);
+    Ok(subscription.status)
+}
+
+#[contractimpl]
+impl SbSubscription {
+    pub fn init(env: Env, admin: Address, payment_token: Address, treasury: Address) {
+        if env.storage().instance().has(&DataKey::Admin) {
+            panic_with_error!(&env, SubscriptionError::AlreadyInitialized);
+        }
+
+        admin.require_auth();
+        env.storage().instance().set(&DataKey::Admin, &admin);
+        env.storage()
+            .instance()
+            .set(&DataKey::PaymentToken, &payment_token);
+        env.storage().instance().set(&DataKey::Treasury, &treasury);
+        write_schema_version(&env, SCHEMA_VERSION);
+
+        events::Initialized {
+            admin,
+            payment_token,
+            treasury,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    /// Replaces the contract code. Storage is kept as-is; if the new code
+    /// bumps `SCHEMA_VERSION`, call `migrate` until it reports the new version.
+    pub fn upgrade(env: Env, caller: Address, new_wasm_hash: BytesN<32>) {
+        caller.require_auth();
+        assert_admin(&env, DEFAULT_MERCHANT, &caller);
+
+        env.deployer()
+            .update_current_contract_wasm(new_wasm_hash.clone());
+
+        report_to_registry(&env, "record_upgrade", &[new_wasm_hash.to_val()]);
+
+        events::Upgraded {
+            new_wasm_hash,
+            schema_version: read_schema_version(&env),
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    /// Registry contract (e.g. `sb_factory`) notified through `record_upgrade`,
+    /// `record_admin` and `record_payment_token` whenever this instance
+    /// upgrades, changes admin or schedules a new payment token.
+    pub fn set_registry(env: Env, caller: Address, registry: Option<Address>) {
+        caller.require_auth();
+        assert_admin(&env, DEFAULT_MERCHANT, &caller);
+
+        if let Some(registry) = &registry {
+            env.storage().instance().set(&DataKey::Registry, registry);
+        } else {
+            env.storage().instance().remove(&DataKey::Registry);
+        }
+        bump_instance_ttl(&env);
+
+        events::RegistryChanged {
+            registry,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    pub fn get_registry(env: Env) -> Option<Address> {
+        bump_instance_ttl(&env);
+        env.storage().instance().get(&DataKey::Registry)
+    }
+
+    pub fn schema_version(env: Env) -> u32 {
+        read_schema_version(&env)
+    }
+
+    /// Rewrites plans and subscriptions stored in an older layout, one page of
+    /// the plan and subscriber indexes at a time, starting at offset 0. Returns
+    /// the stored schema version, which only moves to `SCHEMA_VERSION` once the
+    /// last page is done.
+    pub fn migrate(env: Env, caller: Address, offset: u32, limit: u32) -> u32 {
+        caller.require_auth();
+        assert_admin(&env, DEFAULT_MERCHANT, &caller);
+        validate_page_size(&env, limit);
+
+        let from_version = read_schema_version(&env);
+        if from_version >= SCHEMA_VERSION {
+            panic_with_error!(&env, SubscriptionError::AlreadyMigrated);
+        }
+
+        // Pages must follow each other, so the version can't move before
+        // every entry has been rewritten.
+        if offset != read_migration_cursor(&env) {
+            panic_with_error!(&env, SubscriptionError::MigrationOutOfOrder);
+        }
+
+        let plan_count = read_plan_count(&env);
+        let subscriber_count = read_subscriber_count(&env);
+        let end = offset.saturating_add(limit);
+
+        let mut index = offset;
+        while index < min(plan_count, end) {
+            let maybe_plan_id: Option<u32> =
+                env.storage().persistent().get(&DataKey::PlanIndex(index));
+            if let Some(plan_id) = maybe_plan_id {
+                migration::migrate_plan(&env, plan_id);
+            }
+            index += 1;
+        }
+
+        let mut index = offset;
+        while index < min(subscriber_count, end) {
+            let maybe_subscriber: Option<Address> = env
+                .storage()
+                .persistent()
+                .get(&DataKey::SubscriberIndex(index));
+            if let Some(subscriber) = maybe_subscriber {
+                migration::migrate_subscription(&env, subscriber);
+            }
+            index += 1;
+        }
+
+        if end < plan_count || end < subscriber_count {
+            write_migration_cursor(&env, end);
+            return from_version;
+        }
+
+        env.storage().instance().remove(&DataKey::MigrationCursor);
+        write_schema_version(&env, SCHEMA_VERSION);
+        events::Migrated {
+            from_version,
+            to_version: SCHEMA_VERSION,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+        SCHEMA_VERSION
+    }
+
+    /// First step of an admin transfer. The proposal only takes effect once
+    /// `new_admin` signs `accept_admin`, so a typo'd or keyless address can't
+    /// lock the contract. A new proposal replaces the pending one.
+    pub fn propose_admin(env: Env, caller: Address, new_admin: Address) {
+        caller.require_auth();
+        assert_admin(&env, DEFAULT_MERCHANT, &caller);
+
+        env.storage()
+            .instance()
+            .set(&DataKey::PendingAdmin, &new_admin);
+        bump_instance_ttl(&env);
+
+        events::AdminProposed {
+            admin: caller,
+            new_admin,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    pub fn accept_admin(env: Env) {
+        let new_admin = read_pending_admin(&env)
+            .unwrap_or_else(|| panic_with_error!(&env, SubscriptionError::NoPendingAdmin));
+        new_admin.require_auth();
+
+        let previous_admin = read_admin(&env);
+        env.storage().instance().set(&DataKey::Admin, &new_admin);
+        env.storage().instance().remove(&DataKey::PendingAdmin);
+        bump_instance_ttl(&env);
+        report_to_registry(&env, "record_admin", &[new_admin.to_val()]);
+
+        events::AdminChanged {
+            previous_admin,
+            new_admin,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    pub fn cancel_admin_proposal(env: Env, caller: Address) {
+        caller.require_auth();
+        assert_admin(&env, DEFAULT_MERCHANT, &caller);
+
+        let new_admin = read_pending_admin(&env)
+            .unwrap_or_else(|| panic_with_error!(&env, SubscriptionError::NoPendingAdmin));
+        env.storage().instance().remove(&DataKey::PendingAdmin);
+        bump_instance_ttl(&env);
+
+        events::AdminProposalCancelled {
+            admin: caller,
+            new_admin,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    pub fn get_admin(env: Env) -> Address {
+        read_admin(&env)
+    }
+
+    pub fn get_pending_admin(env: Env) -> Option<Address> {
+        read_pending_admin(&env)
+    }
+
+    /// Takes effect at once: every later charge is paid to `treasury`.
+    pub fn set_treasury(env: Env, caller: Address, treasury: Address) {
+        caller.require_auth();
+        assert_admin(&env, DEFAULT_MERCHANT, &caller);
+
+        let previous_treasury = read_treasury(&env);
+        env.storage().instance().set(&DataKey::Treasury, &treasury);
+
+        events::TreasuryChanged {
+            treasury,
+            previous_treasury,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    /// Schedules a switch to `token`. New subscriptions and renewals made from
+    /// `effective_ledger` on are charged in `token`; existing subscriptions keep
+    /// paying in the token recorded on them until then. Scheduling again
+    /// replaces a change that has not taken effect yet.
+    pub fn schedule_payment_token(
+        env: Env,
+        caller: Address,
+        token: Address,
+        effective_ledger: u32,
+    ) {
+        caller.require_auth();
+        assert_admin(&env, DEFAULT_MERCHANT, &caller);
+
+        if effective_ledger < env.ledger().sequence() {
+            panic_with_error!(&env, SubscriptionError::InvalidEffectiveLedger);
+        }
+
+        // Fold a change that already took effect into the current token so
+        // it is not lost when the new one is stored.
+        let previous_token = read_payment_token(&env);
+        env.storage()
+            .instance()
+            .set(&DataKey::PaymentToken, &previous_token);
+        env.storage().instance().set(
+            &DataKey::PaymentTokenChange,
+            &PaymentTokenChange {
+                token: token.clone(),
+                effective_ledger,
+            },
+        );
+        bump_instance_ttl(&env);
+        report_to_registry(
+            &env,
+            "record_payment_token",
+            &[token.to_val(), effective_ledger.into()],
+        );
+
+        events::PaymentTokenChangeScheduled {
+            token,
+            previous_token,
+            effective_ledger,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    pub fn get_treasury(env: Env) -> Address {
+        read_treasury(&env)
+    }
+
+    /// The token new charges are made in right now.
+    pub fn get_payment_token(env: Env) -> Address {
+        read_payment_token(&env)
+    }
+
+    /// The scheduled payment token change, until it takes effect.
+    pub fn get_payment_token_change(env: Env) -> Option<PaymentTokenChange> {
+        read_payment_token_change(&env)
+            .filter(|change| env.ledger().sequence() < change.effective_ledger)
+    }
+
+    /// Hosts another merchant in this deployment. Only the contract admin can
+    /// register merchants; each then manages its own plans and settings.
+    pub fn register_merchant(
+        env: Env,
+        caller: Address,
+        merchant_id: u32,
+        admin: Address,
+        treasury: Address,
+        payment_token: Address,
+    ) {
+        caller.require_auth();
+        assert_admin(&env, DEFAULT_MERCHANT, &caller);
+
+        let key = DataKey::Merchant(merchant_id);
+        if merchant_id == DEFAULT_MERCHANT || env.storage().persistent().has(&key) {
+            panic_with_error!(&env, SubscriptionError::MerchantAlreadyExists);
+        }
+
+        let merchant = Merchant {
+            id: merchant_id,
+            admin,
+            treasury,
+            payment_token,
+        };
+        env.storage().persistent().set(&key, &merchant);
+        bump_persistent_ttl(&env, &key);
+
+        events::MerchantRegistered {
+            merchant_id,
+            admin: merchant.admin,
+            treasury: merchant.treasury,
+            payment_token: merchant.payment_token,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    /// Called by the merchant admin. A new admin must co-sign, and a new
+    /// payment token applies to each subscription from its next renewal.
+    pub fn update_merchant(
+        env: Env,
+        caller: Address,
+        merchant_id: u32,
+        admin: Address,
+        treasury: Address,
+        payment_token: Address,
+    ) {
+        caller.require_auth();
+        if merchant_id == DEFAULT_MERCHANT {
+            panic_with_error!(&env, SubscriptionError::MerchantNotFound);
+        }
+        assert_admin(&env, merchant_id, &caller);
+        if admin != caller {
+            admin.require_auth();
+        }
+
+        let key = DataKey::Merchant(merchant_id);
+        let merchant = Merchant {
+            id: merchant_id,
+            admin,
+            treasury,
+            payment_token,
+        };
+        env.storage().persistent().set(&key, &merchant);
+        bump_persistent_ttl(&env, &key);
+
+        events::MerchantUpdated {
+            merchant_id,
+            admin: merchant.admin,
+            treasury: merchant.treasury,
+            payment_token: merchant.payment_token,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    pub fn get_merchant(env: Env, merchant_id: u32) -> Merchant {
+        read_merchant(&env, merchant_id)
+    }
+
+    pub fn create_plan(
+        env: Env,
+        caller: Address,
+        plan_id: u32,
+        name: String,
+        period_ledgers: u32,
+        price_stroops: i128,
+    ) {
+        insert_plan(
+            &env,
+            &caller,
+            DEFAULT_MERCHANT,
+            plan_id,
+            name,
+            period_ledgers,
+            price_stroops,
+        );
+    }
+
+    /// Creates a plan billed to and administered by `merchant_id` and returns
+    /// its id. Ids are assigned here, so merchants can't take each other's.
+    pub fn create_merchant_plan(
+        env: Env,
+        caller: Address,
+        merchant_id: u32,
+        name: String,
+        period_ledgers: u32,
+        price_stroops: i128,
+    ) -> u32 {
+        read_merchant(&env, merchant_id);
+        let mut plan_id = read_plan_count(&env) + 1;
+        while env.storage().persistent().has(&DataKey::Plan(plan_id)) {
+            plan_id += 1;
+        }
+        insert_plan(
+            &env,
+            &caller,
+            merchant_id,
+            plan_id,
+            name,
+            period_ledgers,
+            price_stroops,
+        );
+        plan_id
+    }
+
+    pub fn set_plan_status(env: Env, caller: Address, plan_id: u32, active: bool) {
+        let mut plan = read_admin_plan(&env, &caller, plan_id);
+
+        plan.active = active;
+        write_plan(&env, &plan);
+
+        events::PlanStatusChanged {
+            plan_id,
+            active,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    /// Sets how long the plan's billing period is. Time-based periods also
+    /// update `period_ledgers` to an estimate at 5 seconds per ledger.
+    /// Existing subscriptions pick the new period up at their next renewal.
+    pub fn set_plan_period(env: Env, caller: Address, plan_id: u32, period: BillingPeriod) {
+        let mut plan = read_admin_plan(&env, &caller, plan_id);
+        let empty = match period {
+            BillingPeriod::Seconds(seconds) => seconds == 0,
+            BillingPeriod::Ledgers(count)
+            | BillingPeriod::Weeks(count)
+            | BillingPeriod::Months(count)
+            | BillingPeriod::Years(count) => count == 0,
+        };
+        if empty {
+            panic_with_error!(&env, SubscriptionError::InvalidPeriod);
+        }
+
+        plan.billing_period = period;
+        plan.period_ledgers = estimated_period_ledgers(period);
+        write_plan(&env, &plan);
+
+        events::PlanPeriodChanged {
+            plan_id,
+            period,
+            period_ledgers: plan.period_ledgers,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    /// Sets what happens to periods missed by renewals made more than a whole
+    /// period late. Plans default to `ChargeLatest`.
+    pub fn set_plan_catch_up(env: Env, caller: Address, plan_id: u32, policy: CatchUpPolicy) {
+        let mut plan = read_admin_plan(&env, &caller, plan_id);
+
+        plan.catch_up_policy = policy;
+        write_plan(&env, &plan);
+
+        events::PlanCatchUpChanged {
+            plan_id,
+            policy,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    /// Allows `max_pauses` pauses per subscription (0 disables pausing) of
+    /// at most `max_pause_ledgers` each (0 for no limit).
+    pub fn set_plan_pause(
+        env: Env,
+        caller: Address,
+        plan_id: u32,
+        max_pauses: u32,
+        max_pause_ledgers: u32,
+    ) {
+        let mut plan = read_admin_plan(&env, &caller, plan_id);
+
+        plan.max_pauses = max_pauses;
+        plan.max_pause_ledgers = max_pause_ledgers;
+        write_plan(&env, &plan);
+
+        events::PlanPauseChanged {
+            plan_id,
+            max_pauses,
+            max_pause_ledgers,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    pub fn set_plan_trial(env: Env, caller: Address, plan_id: u32, trial_ledgers: u32) {
+        let mut plan = read_admin_plan(&env, &caller, plan_id);
+
+        plan.trial_ledgers = trial_ledgers;
+        write_plan(&env, &plan);
+
+        events::PlanTrialChanged {
+            plan_id,
+            trial_ledgers,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    /// Configures dunning for failed renewals: a past-due subscription keeps
+    /// access for `grace_ledgers` after its due ledger and is suspended after
+    /// `max_failed_renewals` failed attempts, whichever comes first (0 turns
+    /// either limit off). Retries are spaced by `retry_interval_ledgers`.
+    pub fn set_plan_dunning(
+        env: Env,
+        caller: Address,
+        plan_id: u32,
+        grace_ledgers: u32,
+        max_failed_renewals: u32,
+        retry_interval_ledgers: u32,
+    ) {
+        let mut plan = read_admin_plan(&env, &caller, plan_id);
+
+        plan.grace_ledgers = grace_ledgers;
+        plan.max_failed_renewals = max_failed_renewals;
+        plan.retry_interval_ledgers = retry_interval_ledgers;
+        write_plan(&env, &plan);
+
+        events::PlanDunningChanged {
+            plan_id,
+            grace_ledgers,
+            max_failed_renewals,
+            retry_interval_ledgers,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    /// Replaces the plan's descriptive fields. Price, period, token and
+    /// dunning settings have their own entrypoints.
+    pub fn update_plan(env: Env, caller: Address, plan_id: u32, details: PlanDetails) {
+        let mut plan = read_admin_plan(&env, &caller, plan_id);
+
+        plan.name = details.name.clone();
+        plan.description = details.description.clone();
+        plan.metadata_uri = details.metadata_uri.clone();
+        plan.metadata_hash = details.metadata_hash.clone();
+        plan.display_order = details.display_order;
+        plan.max_active_subscribers = details.max_active_subscribers;
+        write_plan(&env, &plan);
+
+        events::PlanUpdated {
+            plan_id,
+            details,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    /// Publishes a new price version for the plan. New subscriptions pay it
+    /// at once; existing ones follow `policy`.
+    pub fn update_plan_price(
+        env: Env,
+        caller: Address,
+        plan_id: u32,
+        price_stroops: i128,
+        policy: PriceChangePolicy,
+    ) -> u32 {
+        let mut plan = read_admin_plan(&env, &caller, plan_id);
+        if price_stroops <= 0 {
+            panic_with_error!(&env, SubscriptionError::InvalidPrice);
+        }
+
+        let notice_ledgers = match policy {
+            PriceChangePolicy::Grandfathered => 0,
+            PriceChangePolicy::NextRenewalAfter(notice_ledgers) => notice_ledgers,
+        };
+        let price = PlanPrice {
+            version: plan.price_version + 1,
+            price_stroops,
+            policy,
+            effective_ledger: env.ledger().sequence().saturating_add(notice_ledgers),
+        };
+        write_price_version(&env, plan_id, &price);
+        plan.price_stroops = price_stroops;
+        plan.price_version = price.version;
+        write_plan(&env, &plan);
+
+        events::PlanPriceUpdated {
+            plan_id,
+            price_version: price.version,
+            price_stroops,
+            policy,
+            effective_ledger: price.effective_ledger,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+        price.version
+    }
+
+    /// Creates a coupon for `merchant_id`'s plans, redeemable through
+    /// `subscribe` under `code`. Codes are scoped to their merchant.
+    pub fn create_coupon(
+        env: Env,
+        caller: Address,
+        merchant_id: u32,
+        code: Symbol,
+        terms: CouponTerms,
+    ) {
+        caller.require_auth();
+        assert_admin(&env, merchant_id, &caller);
+
+        let key = DataKey::Coupon(merchant_id, code.clone());
+        if env.storage().persistent().has(&key) {
+            panic_with_error!(&env, SubscriptionError::CouponAlreadyExists);
+        }
+
+        let valid_discount = match terms.discount {
+            CouponDiscount::BasisPoints(bps) => bps > 0 && bps <= 10_000,
+            CouponDiscount::Fixed(fixed) => fixed > 0,
+        };
+        let valid_duration = terms.duration != CouponDuration::Periods(0);
+        let valid_expiry = terms
+            .expires_ledger
+            .is_none_or(|expires| expires >= env.ledger().sequence());
+        if !valid_discount || !valid_duration || !valid_expiry {
+            panic_with_error!(&env, SubscriptionError::InvalidCoupon);
+        }
+        for plan_id in terms.plan_ids.iter() {
+            if read_plan(&env, plan_id).merchant_id != merchant_id {
+                panic_with_error!(&env, SubscriptionError::InvalidCoupon);
+            }
+        }
+
+        let coupon = Coupon {
+            code: code.clone(),
+            merchant_id,
+            terms,
+            redemptions: 0,
+            active: true,
+        };
+        env.storage().persistent().set(&key, &coupon);
+        bump_persistent_ttl(&env, &key);
+
+        events::CouponCreated {
+            code,
+            merchant_id,
+            terms: coupon.terms,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    /// Deactivating a coupon stops new redemptions; subscriptions that already
+    /// redeemed it keep their discount.
+    pub fn set_coupon_status(
+        env: Env,
+        caller: Address,
+        merchant_id: u32,
+        code: Symbol,
+        active: bool,
+    ) {
+        caller.require_auth();
+        assert_admin(&env, merchant_id, &caller);
+
+        let key = DataKey::Coupon(merchant_id, code.clone());
+        let mut coupon: Coupon = env
+            .storage()
+            .persistent()
+            .get(&key)
+            .unwrap_or_else(|| panic_with_error!(&env, SubscriptionError::CouponNotFound));
+
+        coupon.active = active;
+        env.storage().persistent().set(&key, &coupon);
+        bump_persistent_ttl(&env, &key);
+
+        events::CouponStatusChanged {
+            code,
+            merchant_id,
+            active,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    pub fn get_coupon(env: Env, merchant_id: u32, code: Symbol) -> Coupon {
+        let key = DataKey::Coupon(merchant_id, code);
+        let coupon = env
+            .storage()
+            .persistent()
+            .get(&key)
+            .unwrap_or_else(|| panic_with_error!(&env, SubscriptionError::CouponNotFound));
+        bump_persistent_ttl(&env, &key);
+        coupon
+    }
+
+    /// Allowlists (or delists) a token plans may be priced in. Delisting stops
+    /// new subscriptions to plans using the token; existing ones keep renewing.
+    pub fn set_token_allowed(env: Env, caller: Address, token: Address, allowed: bool) {
+        caller.require_auth();
+        assert_admin(&env, DEFAULT_MERCHANT, &caller);
+
+        let key = DataKey::AllowedToken(token.clone());
+        if allowed {
+            env.storage().persistent().set(&key, &true);
+            bump_persistent_ttl(&env, &key);
+        } else {
+            env.storage().persistent().remove(&key);
+        }
+
+        events::TokenAllowlistChanged {
+            token,
+            allowed,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    pub fn is_token_allowed(env: Env, token: Address) -> bool {
+        is_token_allowed(&env, &token)
+    }
+
+    /// Prices the plan in `token`, which must be allowlisted, or in the
+    /// contract payment token when `None`. Existing subscriptions switch at
+    /// their next renewal.
+    pub fn set_plan_token(env: Env, caller: Address, plan_id: u32, token: Option<Address>) {
+        let mut plan = read_admin_plan(&env, &caller, plan_id);
+
+        if let Some(token) = &token {
+            if !is_token_allowed(&env, token) {
+                panic_with_error!(&env, SubscriptionError::TokenNotAllowed);
+            }
+        }
+
+        plan.payment_token = token.clone();
+        write_plan(&env, &plan);
+
+        events::PlanTokenChanged {
+            plan_id,
+            token,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    /// Sets or clears (`None`) the reward paid to whoever triggers a
+    /// successful renewal of one of the merchant's plans. Each merchant admin
+    /// decides what its own renewals pay out.
+    pub fn set_keeper_reward(
+        env: Env,
+        caller: Address,
+        merchant_id: u32,
+        reward: Option<KeeperReward>,
+    ) {
+        caller.require_auth();
+        assert_admin(&env, merchant_id, &caller);
+
+        let key = DataKey::KeeperReward(merchant_id);
+
+        if let Some(reward) = reward {
+            let valid = match reward.amount {
+                KeeperRewardAmount::BasisPoints(bps) => bps > 0 && bps <= 10_000,
+                KeeperRewardAmount::Fixed(fixed) => fixed > 0,
+            };
+            if !valid {
+                panic_with_error!(&env, SubscriptionError::InvalidKeeperReward);
+            }
+            env.storage().persistent().set(&key, &reward);
+            bump_persistent_ttl(&env, &key);
+        } else {
+            env.storage().persistent().remove(&key);
+        }
+
+        events::KeeperRewardChanged {
+            merchant_id,
+            reward,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    pub fn get_keeper_reward(env: Env, merchant_id: u32) -> Option<KeeperReward> {
+        read_keeper_reward(&env, merchant_id)
+    }
+
+    /// Registers or clears the contract called on the merchant's
+    /// subscription lifecycle transitions.
+    pub fn set_lifecycle_hook(
+        env: Env,
+        caller: Address,
+        merchant_id: u32,
+        hook: Option<LifecycleHook>,
+    ) {
+        caller.require_auth();
+        assert_admin(&env, merchant_id, &caller);
+
+        let key = DataKey::LifecycleHook(merchant_id);
+        if let Some(hook) = &hook {
+            env.storage().persistent().set(&key, hook);
+            bump_persistent_ttl(&env, &key);
+        } else {
+            env.storage().persistent().remove(&key);
+        }
+
+        events::LifecycleHookChanged {
+            merchant_id,
+            hook,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    pub fn get_lifecycle_hook(env: Env, merchant_id: u32) -> Option<LifecycleHook> {
+        hook::read_lifecycle_hook(&env, merchant_id)
+    }
+
+    /// `coupon_code` redeems a coupon for this subscription; its discount then
+    /// applies to every charge it covers, renewals included, until it runs out.
+    pub fn subscribe(env: Env, subscriber: Address, plan_id: u32, coupon_code: Option<Symbol>) {
+        subscriber.require_auth();
+
+        let plan_key = DataKey::Plan(plan_id);
+        let plan: Plan = env
+            .storage()
+            .persistent()
+            .get(&plan_key)
+            .unwrap_or_else(|| panic_with_error!(&env, SubscriptionError::PlanNotFound));
+
+        if !plan.active {
+            panic_with_error!(&env, SubscriptionError::PlanInactive);
+        }
+        let payment_token = plan_payment_token(&env, &plan);
+        if plan.payment_token.is_some() && !is_token_allowed(&env, &payment_token) {
+            panic_with_error!(&env, SubscriptionError::TokenNotAllowed);
+        }
+        let coupon_periods_remaining = match &coupon_code {
+            Some(code) => {
+                redeem_coupon(&env, code, &plan).unwrap_or_else(|err| panic_with_error!(&env, err))
+            }
+            None => None,
+        };
+
+        let subscription_key = DataKey::Subscription(subscriber.clone());
+        let mut credit_stroops = 0;
+        if env.storage().persistent().has(&subscription_key) {
+            let mut existing: Subscription = env
+                .storage()
+                .persistent()
+                .get(&subscription_key)
+                .unwrap_or_else(|| {
+                    panic_with_error!(&env, SubscriptionError::SubscriptionNotFound)
+                });
+            settle_and_free_seat(&env, &mut existing);
+            if existing.active || existing.status == SubscriptionStatus::Paused {
+                panic_with_error!(&env, SubscriptionError::SubscriptionAlreadyExists);
+            }
+            // Credit is kept in the token it was paid in and is not carried
+            // over to a plan priced in another one.
+            if existing.payment_token == payment_token {
+                credit_stroops = existing.credit_stroops;
+            }
         }
 
-        charge_subscription_fee_with_allowance(&env, &subscriber, plan.price_stroops);
+        take_seat(&env, &plan).unwrap_or_else(|err| panic_with_error!(&env, err));
+
+        // Each address gets a single trial per plan; later subscriptions to
+        // the same plan are charged up front like any other.
+        let current_ledger = env.ledger().sequence();
+        let trial_key = DataKey::TrialUsed(subscriber.clone(), plan_id);
+        let with_trial = plan.trial_ledgers > 0 && !env.storage().persistent().has(&trial_key);
+        let mut subscription = Subscription {
+            subscriber: subscriber.clone(),
+            plan_id,
+            started_ledger: current_ledger,
+            next_renewal_ledger: current_ledger,
+            next_renewal_timestamp: None,
+            billing_anchor_timestamp: None,
+            active: true,
+            status: SubscriptionStatus::Active,
+            failed_renewals: 0,
+            last_failed_ledger: 0,
+            last_charge_stroops: 0,
+            credit_stroops,
+            pending_plan_id: None,
+            cancel_at_ledger: None,
+            payment_token,
+            coupon_code: coupon_code.clone(),
+            coupon_periods_remaining,
+            price_version: plan.price_version,
+            paused_ledger: None,
+            paused_timestamp: 0,
+            pause_count: 0,
+            paid_stroops: 0,
+            refunded_stroops: 0,
+        };
+        let amount = if with_trial {
+            env.storage().persistent().set(&trial_key, &true);
+            bump_persistent_ttl(&env, &trial_key);
+            subscription.next_renewal_ledger += plan.trial_ledgers;
+            if !matches!(plan.billing_period, BillingPeriod::Ledgers(_)) {
+                subscription.next_renewal_timestamp =
+                    Some(env.ledger().timestamp() + plan.trial_ledgers as u64 * SECONDS_PER_LEDGER);
+            }
+            0
+        } else {
+            let price = discounted_price(&env, &subscription, &plan, plan.price_stroops);
+            let amount = apply_credit(&mut subscription, price);
+            if amount > 0 {
+                let treasury = read_merchant(&env, plan.merchant_id).treasury;
+                charge_subscription_fee(
+                    &env,
+                    &subscription.payment_token,
+                    &treasury,
+                    &subscriber,
+                    amount,
+                );
+            }
+            subscription.last_charge_stroops = price;
+            start_renewal_schedule(&env, &mut subscription, &plan);
+            use_coupon_period(&env, &mut subscription, &plan);
+            record_payment(&env, &mut subscription, amount, current_ledger);
+            amount
+        };
 
-        subscription.next_renewal_ledger = current_ledger + plan.period_ledgers;
-        env.storage().persistent().set(&subscription_key, &subscription);
+        env.storage()
+            .persistent()
+            .set(&subscription_key, &subscription);
+        index_subscriber_if_needed(&env, &subscriber);
         bump_persistent_ttl(&env, &plan_key);
         bump_persistent_ttl(&env, &subscription_key);
+
+        if let Some(code) = coupon_code {
+            events::CouponRedeemed {
+                code,
+                subscriber: subscriber.clone(),
+                plan_id,
+                version: EVENT_VERSION,
+            }
+            .publish(&env);
+        }
+        events::Subscribed {
+            subscriber,
+            plan_id,
+            amount,
+            ledger: current_ledger,
+            next_renewal_ledger: subscription.next_renewal_ledger,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+        hook::notify(
+            &env,
+            plan.merchant_id,
+            &subscription,
+            LifecycleEvent::Subscribed,
+        )
+        .unwrap_or_else(|err| panic_with_error!(&env, err));
+    }
+
+    /// `keeper` is the address credited with the keeper reward, if any; it
+    /// does not need to authorize the call.
+    pub fn renew(env: Env, keeper: Address, subscriber: Address) -> SubscriptionStatus {
+        renew_subscription(&env, &keeper, subscriber)
+            .unwrap_or_else(|err| panic_with_error!(&env, err))
+    }
+
+    /// Renews every subscription in `subscribers` that is due. Addresses that
+    /// cannot be renewed are reported with the error code `renew` would have
+    /// failed with, without aborting the rest of the batch.
+    pub fn renew_many(env: Env, keeper: Address, subscribers: Vec<Address>) -> Vec<RenewalResult> {
+        if subscribers.len() > MAX_BATCH_SIZE {
+            panic_with_error!(&env, SubscriptionError::BatchTooLarge);
+        }
+
+        let mut results = Vec::new(&env);
+        for subscriber in subscribers.iter() {
+            let outcome = match renew_subscription(&env, &keeper, subscriber.clone()) {
+                Ok(status) => RenewalOutcome::Processed(status),
+                Err(err) => RenewalOutcome::Skipped(err as u32),
+            };
+            results.push_back(RenewalResult {
+                subscriber,
+                outcome,
+            });
+        }
+        results
+    }
+
+    /// Moves a subscription to another plan without losing `started_ledger`.
+    /// Immediate changes credit the unused part of the current period against
+    /// the new price; surplus credit is consumed by later renewals.
+    pub fn change_plan(env: Env, subscriber: Address, new_plan_id: u32, timing: PlanChangeTiming) {
+        subscriber.require_auth();
+
+        let subscription_key = DataKey::Subscription(subscriber.clone());
+        let mut subscription: Subscription = env
+            .storage()
+            .persistent()
+            .get(&subscription_key)
+            .unwrap_or_else(|| panic_with_error!(&env, SubscriptionError::SubscriptionNotFound));
+        settle_subscription(&env, &mut subscription);
+        if subscription.status == SubscriptionStatus::Paused {
+            panic_with_error!(&env, SubscriptionError::SubscriptionPaused);
+        }
+        if subscription.status != SubscriptionStatus::Active
+            || subscription.cancel_at_ledger.is_some()
+        {
+            panic_with_error!(&env, SubscriptionError::SubscriptionCancelled);
+        }
+        // Picking the current plan again only makes sense to drop a scheduled
+        // change, which then goes through the `NextRenewal` path below.
+        let timing = if new_plan_id == subscription.plan_id {
+            if subscription.pending_plan_id.is_none() {
+                panic_with_error!(&env, SubscriptionError::InvalidPlanChange);
+            }
+            PlanChangeTiming::NextRenewal
+        } else {
+            timing
+        };
+
+        let new_plan = read_plan(&env, new_plan_id);
+        if !new_plan.active {
+            panic_with_error!(&env, SubscriptionError::PlanInactive);
+        }
+        // Proration credit can't be moved across currencies; switching to a
+        // plan priced in another token means cancelling and subscribing again.
+        let current_plan = read_plan(&env, subscription.plan_id);
+        if new_plan.merchant_id != current_plan.merchant_id
+            || plan_payment_token(&env, &new_plan) != plan_payment_token(&env, &current_plan)
+        {
+            panic_with_error!(&env, SubscriptionError::InvalidPlanChange);
+        }
+        // The seat moves to the new plan right away, even when the change
+        // itself waits for the next renewal.
+        if seat_plan_id(&subscription) != new_plan_id {
+            take_seat(&env, &new_plan).unwrap_or_else(|err| panic_with_error!(&env, err));
+            release_seat(&env, seat_plan_id(&subscription));
+        }
+
+        let current_ledger = env.ledger().sequence();
+        match timing {
+            PlanChangeTiming::NextRenewal => {
+                subscription.pending_plan_id =
+                    Some(new_plan_id).filter(|id| *id != subscription.plan_id);
+                env.storage()
+                    .persistent()
+                    .set(&subscription_key, &subscription);
+                bump_persistent_ttl(&env, &subscription_key);
+
+                events::PlanChangeScheduled {
+                    subscriber,
+                    from_plan_id: subscription.plan_id,
+                    to_plan_id: new_plan_id,
+                    effective_ledger: subscription.next_renewal_ledger,
+                    version: EVENT_VERSION,
+                }
+                .publish(&env);
+            }
+            PlanChangeTiming::Immediate => {
+                // Refunds already paid part of the unused value back.
+                subscription.credit_stroops += (unused_period_value(
+                    &env,
+                    &subscription,
+                    &current_plan,
+                    subscription.last_charge_stroops,
+                ) - subscription.refunded_stroops)
+                    .max(0);
+                // The credit was paid in the subscription's token, so the
+                // difference is too; a token rotation applies at renewal.
+                let price =
+                    discounted_price(&env, &subscription, &new_plan, new_plan.price_stroops);
+                let amount = apply_credit(&mut subscription, price);
+                if amount > 0 {
+                    let treasury = read_merchant(&env, new_plan.merchant_id).treasury;
+                    charge_subscription_fee(
+                        &env,
+                        &subscription.payment_token,
+                        &treasury,
+                        &subscriber,
+                        amount,
+                    );
+                }
+
+                let from_plan_id = subscription.plan_id;
+                subscription.plan_id = new_plan_id;
+                subscription.pending_plan_id = None;
+                subscription.last_charge_stroops = price;
+                subscription.price_version = new_plan.price_version;
+                use_coupon_period(&env, &mut subscription, &new_plan);
+                start_renewal_schedule(&env, &mut subscription, &new_plan);
+                record_payment(&env, &mut subscription, amount, current_ledger);
+                env.storage()
+                    .persistent()
+                    .set(&subscription_key, &subscription);
+                bump_persistent_ttl(&env, &subscription_key);
+
+                events::PlanChanged {
+                    subscriber,
+                    from_plan_id,
+                    to_plan_id: new_plan_id,
+                    amount,
+                    credit_stroops: subscription.credit_stroops,
+                    ledger: current_ledger,
+                    next_renewal_ledger: subscription.next_renewal_ledger,
+                    version: EVENT_VERSION,
+                }
+                .publish(&env);
+            }
+        }
+    }
+
+    /// Stops billing and access until `resume`, keeping the paid time left.
+    pub fn pause(env: Env, subscriber: Address) {
+        subscriber.require_auth();
+
+        let mut subscription = read_subscription(&env, &subscriber);
+        if subscription.status == SubscriptionStatus::Paused {
+            panic_with_error!(&env, SubscriptionError::SubscriptionPaused);
+        }
+        if subscription.status != SubscriptionStatus::Active
+            || subscription.cancel_at_ledger.is_some()
+        {
+            panic_with_error!(&env, SubscriptionError::SubscriptionCancelled);
+        }
+        let plan = read_plan(&env, subscription.plan_id);
+        if subscription.pause_count >= plan.max_pauses {
+            panic_with_error!(&env, SubscriptionError::PauseLimitReached);
+        }
+
+        let current_ledger = env.ledger().sequence();
+        subscription.paused_ledger = Some(current_ledger);
+        subscription.paused_timestamp = env.ledger().timestamp();
+        subscription.pause_count += 1;
+        set_status(&mut subscription, SubscriptionStatus::Paused);
+        write_subscription(&env, &subscription);
+
+        events::Paused {
+            subscriber,
+            plan_id: subscription.plan_id,
+            ledger: current_ledger,
+            resume_ledger: (plan.max_pause_ledgers > 0)
+                .then(|| current_ledger.saturating_add(plan.max_pause_ledgers)),
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    pub fn resume(env: Env, subscriber: Address) {
+        subscriber.require_auth();
+
+        let mut subscription = read_subscription(&env, &subscriber);
+        let Some(paused_ledger) = subscription.paused_ledger else {
+            panic_with_error!(&env, SubscriptionError::SubscriptionNotPaused);
+        };
+
+        let current_ledger = env.ledger().sequence();
+        let paused_seconds = env.ledger().timestamp() - subscription.paused_timestamp;
+        resume_subscription(
+            &mut subscription,
+            current_ledger - paused_ledger,
+            paused_seconds,
+        );
+        write_subscription(&env, &subscription);
+
+        events::Resumed {
+            subscriber,
+            plan_id: subscription.plan_id,
+            ledger: current_ledger,
+            next_renewal_ledger: subscription.next_renewal_ledger,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
     }
 
     pub fn cancel(env: Env, subscriber: Address) {
         subscriber.require_auth();
 
-        let key = DataKey::Subscription(subscriber);
+        let mut subscription = read_subscription(&env, &subscriber);
+        if subscription.status == SubscriptionStatus::Paused {
+            panic_with_error!(&env, SubscriptionError::SubscriptionPaused);
+        }
+        if !subscription.active || subscription.cancel_at_ledger.is_some() {
+            panic_with_error!(&env, SubscriptionError::SubscriptionCancelled);
+        }
+
+        // The subscriber keeps access to the period already paid for; renewals
+        // stop and the subscription ends when the next renewal would be due.
+        let current_ledger = env.ledger().sequence();
+        release_seat(&env, seat_plan_id(&subscription));
+        subscription.cancel_at_ledger = Some(subscription.next_renewal_ledger);
+        subscription.pending_plan_id = None;
+        settle_subscription(&env, &mut subscription);
+        write_subscription(&env, &subscription);
+
+        events::Cancelled {
+            subscriber,
+            plan_id: subscription.plan_id,
+            ledger: current_ledger,
+            next_renewal_ledger: subscription.next_renewal_ledger,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+        let _ = hook::notify(
+            &env,
+            read_plan(&env, subscription.plan_id).merchant_id,
+            &subscription,
+            LifecycleEvent::Cancelled,
+        );
+    }
+
+    pub fn uncancel(env: Env, subscriber: Address) {
+        subscriber.require_auth();
+
+        let mut subscription = read_subscription(&env, &subscriber);
+        if subscription.cancel_at_ledger.is_none() {
+            panic_with_error!(&env, SubscriptionError::SubscriptionNotCancelled);
+        }
+        if !subscription.active {
+            panic_with_error!(&env, SubscriptionError::SubscriptionCancelled);
+        }
+        take_seat(&env, &read_plan(&env, subscription.plan_id))
+            .unwrap_or_else(|err| panic_with_error!(&env, err));
+
+        subscription.cancel_at_ledger = None;
+        write_subscription(&env, &subscription);
+
+        events::Uncancelled {
+            subscriber,
+            plan_id: subscription.plan_id,
+            ledger: env.ledger().sequence(),
+            next_renewal_ledger: subscription.next_renewal_ledger,
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+    }
+
+    /// Pays `amount` back to the subscriber from the merchant's treasury,
+    /// which must authorize the transfer, and returns it. At most what the
+    /// subscriber paid for the current period can be refunded. Access and
+    /// billing are not affected.
+    pub fn refund(env: Env, caller: Address, subscriber: Address, amount: RefundAmount) -> i128 {
+        caller.require_auth();
+
         let mut subscription: Subscription = env
             .storage()
             .persistent()
-            .get(&key)
+            .get(&DataKey::Subscription(subscriber.clone()))
             .unwrap_or_else(|| panic_with_error!(&env, SubscriptionError::SubscriptionNotFound));
+        settle_and_free_seat(&env, &mut subscription);
+        let plan = read_plan(&env, subscription.plan_id);
+        assert_admin(&env, plan.merchant_id, &caller);
 
-        subscription.active = false;
-        env.storage().persistent().set(&key, &subscription);
-        bump_persistent_ttl(&env, &key);
+        // Credit-covered value was never transferred, so it can't come back.
+        let paid = subscription.paid_stroops;
+        let amount = match amount {
+            RefundAmount::Fixed(amount) => amount,
+            RefundAmount::Prorated if subscription.active => {
+                unused_period_value(&env, &subscription, &plan, paid)
+                    - subscription.refunded_stroops
+            }
+            RefundAmount::Prorated => 0,
+        };
+        if amount <= 0 || amount > paid - subscription.refunded_stroops {
+            panic_with_error!(&env, SubscriptionError::InvalidRefund);
+        }
+        let treasury = read_merchant(&env, plan.merchant_id).treasury;
+        treasury.require_auth();
+        token::TokenClient::new(&env, &subscription.payment_token).transfer(
+            &treasury,
+            &subscriber,
+            &amount,
+        );
+
+        subscription.refunded_stroops += amount;
+        write_subscription(&env, &subscription);
+
+        events::Refunded {
+            subscriber,
+            plan_id: subscription.plan_id,
+            token: subscription.payment_token,
+            amount,
+            ledger: env.ledger().sequence(),
+            version: EVENT_VERSION,
+        }
+        .publish(&env);
+        amount
+    }
+
+    /// Whether `subscriber` has access now, to `plan_id` or to any plan when
+    /// `None`. Never fails, so other contracts can gate features on it.
+    pub fn is_entitled(env: Env, subscriber: Address, plan_id: Option<u32>) -> bool {
+        try_read_subscription(&env, &subscriber).is_some_and(|subscription| {
+            plan_id.is_none_or(|plan_id| plan_id == subscription.plan_id)
+                && access_end(&env, &subscription).is_some()
+        })
+    }
+
+    /// Ledger at which the access of `subscriber` ends unless renewed, or
+    /// `None` without access. An estimate on time-based plans.
+    pub fn access_until(env: Env, subscriber: Address) -> Option<u32> {
+        access_end(&env, &try_read_subscription(&env, &subscriber)?)
     }
 
     pub fn get_plan(env: Env, plan_id: u32) -> Plan {
-        let key = DataKey::Plan(plan_id);
-        let plan: Plan = env
-            .storage()
+        read_plan(&env, plan_id)
+    }
+
+    pub fn get_plan_price(env: Env, plan_id: u32, price_version: u32) -> PlanPrice {
+        read_price_version(&env, plan_id, price_version)
+            .unwrap_or_else(|| panic_with_error!(&env, SubscriptionError::PriceVersionNotFound))
+    }
+
+    /// Number of subscriptions holding a seat on the plan, the figure
+    /// `max_active_subscribers` is checked against.
+    pub fn get_active_subscriber_count(env: Env, plan_id: u32) -> u32 {
+        read_active_count(&env, plan_id)
+    }
+
+    pub fn has_used_trial(env: Env, subscriber: Address, plan_id: u32) -> bool {
+        env.storage()
             .persistent()
-            .get(&key)
-            .unwrap_or_else(|| panic_with_error!(&env, SubscriptionError::PlanNotFound));
-        bump_persistent_ttl(&env, &key);
-        plan
+            .has(&DataKey::TrialUsed(subscriber, plan_id))
     }
 
     pub fn get_subscription(env: Env, subscriber: Address) -> Subscription {
-        let key = DataKey::Subscription(subscriber);
-        let subscription: Subscription = env
-            .storage()
-            .persistent()
-            .get(&key)
-            .unwrap_or_else(|| panic_with_error!(&env, SubscriptionError::SubscriptionNotFound));
-        bump_persistent_ttl(&env, &key);
+        let subscription = read_subscription(&env, &subscriber);
+        bump_persistent_ttl(&env, &DataKey::Subscription(subscriber));
         subscription
     }
 
@@ -392,6 +2803,38 @@ impl SbSubscription {
         result
     }
 
+    pub fn list_merchant_plans(env: Env, merchant_id: u32, offset: u32, limit: u32) -> Vec<Plan> {
+        validate_page_size(&env, limit);
+        let mut result = Vec::new(&env);
+        if limit == 0 {
+            return result;
+        }
+
+        let plan_count = read_merchant_plan_count(&env, merchant_id);
+        if offset >= plan_count {
+            return result;
+        }
+
+        let end = min(plan_count, offset.saturating_add(limit));
+        let mut index = offset;
+        while index < end {
+            let index_key = DataKey::MerchantPlanIndex(merchant_id, index);
+            let maybe_plan_id: Option<u32> = env.storage().persistent().get(&index_key);
+            if let Some(plan_id) = maybe_plan_id {
+                let plan_key = DataKey::Plan(plan_id);
+                let maybe_plan: Option<Plan> = env.storage().persistent().get(&plan_key);
+                if let Some(plan) = maybe_plan {
+                    result.push_back(plan);
+                    bump_persistent_ttl(&env, &plan_key);
+                }
+                bump_persistent_ttl(&env, &index_key);
+            }
+            index += 1;
+        }
+
+        result
+    }
+
     pub fn list_subscribers(env: Env, offset: u32, limit: u32) -> Vec<Address> {
         validate_page_size(&env, limit);
         let mut result = Vec::new(&env);
@@ -419,6 +2862,38 @@ impl SbSubscription {
         result
     }
 
+    pub fn get_payment_count(env: Env, subscriber: Address) -> u32 {
+        read_payment_count(&env, &subscriber)
+    }
+
+    /// Pages through the charges collected from `subscriber`, oldest first.
+    pub fn list_payments(env: Env, subscriber: Address, offset: u32, limit: u32) -> Vec<Payment> {
+        validate_page_size(&env, limit);
+        let mut result = Vec::new(&env);
+        if limit == 0 {
+            return result;
+        }
+
+        let payment_count = read_payment_count(&env, &subscriber);
+        if offset >= payment_count {
+            return result;
+        }
+
+        let end = min(payment_count, offset.saturating_add(limit));
+        let mut index = offset;
+        while index < end {
+            let payment_key = DataKey::Payment(subscriber.clone(), index);
+            let maybe_payment: Option<Payment> = env.storage().persistent().get(&payment_key);
+            if let Some(payment) = maybe_payment {
+                result.push_back(payment);
+                bump_persistent_ttl(&env, &payment_key);
+            }
+            index += 1;
+        }
+
+        result
+    }
+
     pub fn list_subscriptions(env: Env, offset: u32, limit: u32) -> Vec<Subscription> {
         validate_page_size(&env, limit);
         let mut result = Vec::new(&env);
@@ -441,7 +2916,8 @@ impl SbSubscription {
                 let subscription_key = DataKey::Subscription(subscriber);
                 let maybe_subscription: Option<Subscription> =
                     env.storage().persistent().get(&subscription_key);
-                if let Some(subscription) = maybe_subscription {
+                if let Some(mut subscription) = maybe_subscription {
+                    settle_subscription(&env, &mut subscription);
                     result.push_back(subscription);
                     bump_persistent_ttl(&env, &subscription_key);
                 }
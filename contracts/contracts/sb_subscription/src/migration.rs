@@ -0,0 +1,44 @@
+use crate::{bump_instance_ttl, DataKey};
+use soroban_sdk::{Address, Env};
+
+/// Storage layout written by this version of the contract.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Deployments that predate schema versioning never stored a version.
+const UNVERSIONED_SCHEMA: u32 = 1;
+
+pub fn read_schema_version(env: &Env) -> u32 {
+    bump_instance_ttl(env);
+    env.storage()
+        .instance()
+        .get(&DataKey::SchemaVersion)
+        .unwrap_or(UNVERSIONED_SCHEMA)
+}
+
+pub fn write_schema_version(env: &Env, version: u32) {
+    bump_instance_ttl(env);
+    env.storage()
+        .instance()
+        .set(&DataKey::SchemaVersion, &version);
+}
+
+/// Offset of the next page `migrate` expects.
+pub fn read_migration_cursor(env: &Env) -> u32 {
+    env.storage()
+        .instance()
+        .get(&DataKey::MigrationCursor)
+        .unwrap_or(0)
+}
+
+pub fn write_migration_cursor(env: &Env, offset: u32) {
+    env.storage()
+        .instance()
+        .set(&DataKey::MigrationCursor, &offset);
+}
+
+/// Rewrites the plan in the current layout. Schema version 2 is the first
+/// layout deployed with `upgrade`, so there is no older one to convert yet.
+pub fn migrate_plan(_env: &Env, _plan_id: u32) {}
+
+/// Rewrites the subscription in the current layout, like `migrate_plan`.
+pub fn migrate_subscription(_env: &Env, _subscriber: Address) {}
@@ -1,11 +1,58 @@
 #![cfg(test)]
+extern crate std;
 
-use crate::{SbSubscription, SbSubscriptionClient};
+use crate::{
+    calendar, events,
+    hook::LifecycleHookInterface,
+    BillingPeriod, CatchUpPolicy, CouponDiscount, CouponDuration, CouponTerms, DataKey,
+    HookFailurePolicy, KeeperReward, KeeperRewardAmount, KeeperRewardSource, LifecycleEvent,
+    LifecycleHook, Payment, PaymentTokenChange, PlanChangeTiming, PlanDetails, PriceChangePolicy,
+    RefundAmount, RenewalOutcome, SbSubscription, SbSubscriptionClient, SubscriptionError,
+    SubscriptionStatus,
+};
 use soroban_sdk::{
-    testutils::{Address as _, Ledger as _},
-    token, Address, Env, String,
+    contract, contractimpl,
+    testutils::{Address as _, Events as _, Ledger as _},
+    token, vec, Address, BytesN, Env, Event as _, String, Symbol, Vec,
 };
 
+struct Setup<'a> {
+    client: SbSubscriptionClient<'a>,
+    contract_id: Address,
+    admin: Address,
+    treasury: Address,
+    keeper: Address,
+    token_admin_client: token::StellarAssetClient<'a>,
+    token_client: token::TokenClient<'a>,
+}
+
+/// Registers and initializes the contract with a fresh payment token.
+fn setup(env: &Env) -> Setup<'_> {
+    env.mock_all_auths();
+    let contract_id = env.register(SbSubscription, ());
+    let client = SbSubscriptionClient::new(env, &contract_id);
+    let admin = Address::generate(env);
+    let treasury = Address::generate(env);
+    let token_admin = Address::generate(env);
+    let token = env
+        .register_stellar_asset_contract_v2(token_admin)
+        .address();
+    let token_admin_client = token::StellarAssetClient::new(env, &token);
+    let token_client = token::TokenClient::new(env, &token);
+
+    client.init(&admin, &token, &treasury);
+
+    Setup {
+        client,
+        contract_id,
+        admin,
+        treasury,
+        keeper: Address::generate(env),
+        token_admin_client,
+        token_client,
+    }
+}
+
 #[test]
 fn full_subscription_lifecycle() {
     let env = Env::default();
@@ -15,6 +62,7 @@ fn full_subscription_lifecycle() {
     let admin = Address::generate(&env);
     let user = Address::generate(&env);
     let treasury = Address::generate(&env);
+    let keeper = Address::generate(&env);
 
     let token_admin = Address::generate(&env);
     let token = env.register_stellar_asset_contract_v2(token_admin);
@@ -34,7 +82,7 @@ fn full_subscription_lifecycle() {
     let user_balance_before_subscribe = token_client.balance(&user);
     let treasury_balance_before_subscribe = token_client.balance(&treasury);
 
-    client.subscribe(&user, &1);
+    client.subscribe(&user, &1, &None);
 
     let user_balance_after_subscribe = token_client.balance(&user);
     let treasury_balance_after_subscribe = token_client.balance(&treasury);
@@ -52,14 +100,14 @@ fn full_subscription_lifecycle() {
     assert_eq!(sub.plan_id, 1);
     assert_eq!(sub.next_renewal_ledger - sub.started_ledger, 30);
 
-    let err = client.try_renew(&user);
+    let err = client.try_renew(&keeper, &user);
     assert!(err.is_err());
 
     let user_balance_before_renew = token_client.balance(&user);
     let treasury_balance_before_renew = token_client.balance(&treasury);
     env.ledger().set_sequence_number(sub.next_renewal_ledger);
     token_client.approve(&user, &contract_id, &1_000_000, &1_000_000);
-    client.renew(&user);
+    client.renew(&keeper, &user);
 
     let user_balance_after_renew = token_client.balance(&user);
     let treasury_balance_after_renew = token_client.balance(&treasury);
@@ -74,6 +122,11 @@ fn full_subscription_lifecycle() {
 
     client.cancel(&user);
     sub = client.get_subscription(&user);
+    assert!(sub.active);
+    assert_eq!(sub.cancel_at_ledger, Some(sub.next_renewal_ledger));
+
+    env.ledger().set_sequence_number(sub.next_renewal_ledger);
+    sub = client.get_subscription(&user);
     assert!(!sub.active);
 }
 
@@ -119,7 +172,7 @@ fn cannot_subscribe_to_inactive_plan() {
     client.create_plan(&admin, &1, &String::from_str(&env, "Starter"), &30, &1_000_000);
     client.set_plan_status(&admin, &1, &false);
 
-    let err = client.try_subscribe(&user, &1);
+    let err = client.try_subscribe(&user, &1, &None);
     assert!(err.is_err());
 }
 
@@ -132,6 +185,7 @@ fn renew_requires_next_due_ledger_and_allowance() {
     let admin = Address::generate(&env);
     let user = Address::generate(&env);
     let treasury = Address::generate(&env);
+    let keeper = Address::generate(&env);
     let token_admin = Address::generate(&env);
     let token = env.register_stellar_asset_contract_v2(token_admin);
     let token_admin_client = token::StellarAssetClient::new(&env, &token.address());
@@ -140,10 +194,10 @@ fn renew_requires_next_due_ledger_and_allowance() {
 
     client.init(&admin, &token.address(), &treasury);
     client.create_plan(&admin, &7, &String::from_str(&env, "Pro"), &15, &2_000_000);
-    client.subscribe(&user, &7);
+    client.subscribe(&user, &7, &None);
 
     // Renew cannot happen before due ledger.
-    let too_early_err = client.try_renew(&user);
+    let too_early_err = client.try_renew(&keeper, &user);
     assert!(too_early_err.is_err());
 
     let sub = client.get_subscription(&user);
@@ -151,7 +205,7 @@ fn renew_requires_next_due_ledger_and_allowance() {
     token_client.approve(&user, &contract_id, &2_000_000, &1_000_000);
     let user_before = token_client.balance(&user);
     let treasury_before = token_client.balance(&treasury);
-    client.renew(&user);
+    client.renew(&keeper, &user);
     assert_eq!(user_before - token_client.balance(&user), 2_000_000);
     assert_eq!(token_client.balance(&treasury) - treasury_before, 2_000_000);
 }
@@ -188,9 +242,9 @@ fn list_plans_and_subscriptions_are_paginated() {
     token_admin_client.mint(&user_1, &5_000_000);
     token_admin_client.mint(&user_2, &5_000_000);
     token_admin_client.mint(&user_3, &5_000_000);
-    client.subscribe(&user_1, &1);
-    client.subscribe(&user_2, &2);
-    client.subscribe(&user_3, &3);
+    client.subscribe(&user_1, &1, &None);
+    client.subscribe(&user_2, &2, &None);
+    client.subscribe(&user_3, &3, &None);
 
     let subscribers_page = client.list_subscribers(&1, &2);
     assert_eq!(subscribers_page.len(), 2);
@@ -202,3 +256,2161 @@ fn list_plans_and_subscriptions_are_paginated() {
     assert_eq!(subscriptions_page.get(0).unwrap().plan_id, 1);
     assert_eq!(subscriptions_page.get(1).unwrap().plan_id, 2);
 }
+
+#[test]
+fn lifecycle_transitions_emit_events() {
+    let env = Env::default();
+    let s = setup(&env);
+    let user = Address::generate(&env);
+    s.token_admin_client.mint(&user, &10_000_000);
+
+    let name = String::from_str(&env, "Starter");
+    s.client.create_plan(&s.admin, &1, &name, &30, &1_000_000);
+    assert_eq!(
+        env.events().all().filter_by_contract(&s.contract_id),
+        [events::PlanCreated {
+            plan_id: 1,
+            name,
+            period_ledgers: 30,
+            price_stroops: 1_000_000,
+            version: events::EVENT_VERSION,
+        }
+        .to_xdr(&env, &s.contract_id)]
+    );
+
+    s.client.set_plan_status(&s.admin, &1, &true);
+    assert_eq!(
+        env.events().all().filter_by_contract(&s.contract_id),
+        [events::PlanStatusChanged {
+            plan_id: 1,
+            active: true,
+            version: events::EVENT_VERSION,
+        }
+        .to_xdr(&env, &s.contract_id)]
+    );
+
+    let subscribed_ledger = env.ledger().sequence();
+    s.client.subscribe(&user, &1, &None);
+    assert_eq!(
+        env.events().all().filter_by_contract(&s.contract_id),
+        [events::Subscribed {
+            subscriber: user.clone(),
+            plan_id: 1,
+            amount: 1_000_000,
+            ledger: subscribed_ledger,
+            next_renewal_ledger: subscribed_ledger + 30,
+            version: events::EVENT_VERSION,
+        }
+        .to_xdr(&env, &s.contract_id)]
+    );
+
+    let renew_ledger = subscribed_ledger + 35;
+    env.ledger().set_sequence_number(renew_ledger);
+    s.token_client
+        .approve(&user, &s.contract_id, &1_000_000, &1_000_000);
+    s.client.renew(&s.keeper, &user);
+    assert_eq!(
+        env.events().all().filter_by_contract(&s.contract_id),
+        [events::Renewed {
+            subscriber: user.clone(),
+            plan_id: 1,
+            amount: 1_000_000,
+            ledger: renew_ledger,
+            // Late renewals don't move the schedule.
+            next_renewal_ledger: subscribed_ledger + 60,
+            version: events::EVENT_VERSION,
+        }
+        .to_xdr(&env, &s.contract_id)]
+    );
+
+    s.client.cancel(&user);
+    assert_eq!(
+        env.events().all().filter_by_contract(&s.contract_id),
+        [events::Cancelled {
+            subscriber: user,
+            plan_id: 1,
+            ledger: renew_ledger,
+            next_renewal_ledger: subscribed_ledger + 60,
+            version: events::EVENT_VERSION,
+        }
+        .to_xdr(&env, &s.contract_id)]
+    );
+}
+
+#[test]
+fn trial_defers_first_charge_and_is_granted_once_per_plan() {
+    let env = Env::default();
+    let s = setup(&env);
+    let user = Address::generate(&env);
+    s.token_admin_client.mint(&user, &10_000_000);
+
+    s.client.create_plan(
+        &s.admin,
+        &1,
+        &String::from_str(&env, "Pro"),
+        &30,
+        &1_000_000,
+    );
+    s.client.set_plan_trial(&s.admin, &1, &14);
+    assert_eq!(s.client.get_plan(&1).trial_ledgers, 14);
+    assert!(!s.client.has_used_trial(&user, &1));
+
+    s.client.subscribe(&user, &1, &None);
+    assert_eq!(s.token_client.balance(&user), 10_000_000);
+    assert!(s.client.has_used_trial(&user, &1));
+    let sub = s.client.get_subscription(&user);
+    assert!(sub.active);
+    assert_eq!(sub.next_renewal_ledger - sub.started_ledger, 14);
+
+    // The first charge happens on renewal at the end of the trial.
+    env.ledger().set_sequence_number(sub.next_renewal_ledger);
+    s.token_client
+        .approve(&user, &s.contract_id, &1_000_000, &1_000_000);
+    s.client.renew(&s.keeper, &user);
+    assert_eq!(s.token_client.balance(&user), 9_000_000);
+
+    // Resubscribing to the same plan does not grant a second trial.
+    s.client.cancel(&user);
+    let sub = s.client.get_subscription(&user);
+    env.ledger().set_sequence_number(sub.next_renewal_ledger);
+    s.client.subscribe(&user, &1, &None);
+    assert_eq!(s.token_client.balance(&user), 8_000_000);
+    let sub = s.client.get_subscription(&user);
+    assert_eq!(sub.next_renewal_ledger - sub.started_ledger, 30);
+}
+
+#[test]
+fn immediate_plan_change_prorates_unused_period() {
+    let env = Env::default();
+    let s = setup(&env);
+    let user = Address::generate(&env);
+    s.token_admin_client.mint(&user, &20_000_000);
+    s.client.create_plan(
+        &s.admin,
+        &1,
+        &String::from_str(&env, "Basic"),
+        &30,
+        &3_000_000,
+    );
+    s.client.create_plan(
+        &s.admin,
+        &2,
+        &String::from_str(&env, "Pro"),
+        &30,
+        &6_000_000,
+    );
+
+    env.ledger().set_sequence_number(100);
+    s.client.subscribe(&user, &1, &None);
+    assert_eq!(s.token_client.balance(&user), 17_000_000);
+
+    // Upgrade with 20 of 30 ledgers left: 2_000_000 of unused value is
+    // credited against the 6_000_000 Pro price.
+    env.ledger().set_sequence_number(110);
+    s.client
+        .change_plan(&user, &2, &PlanChangeTiming::Immediate);
+    assert_eq!(s.token_client.balance(&user), 13_000_000);
+    let sub = s.client.get_subscription(&user);
+    assert_eq!(sub.plan_id, 2);
+    assert_eq!(sub.started_ledger, 100);
+    assert_eq!(sub.next_renewal_ledger, 140);
+    assert_eq!(sub.credit_stroops, 0);
+
+    // Downgrade with 24 of 30 ledgers left: 4_800_000 of unused value covers
+    // the Basic price and leaves 1_800_000 of credit.
+    env.ledger().set_sequence_number(116);
+    s.client
+        .change_plan(&user, &1, &PlanChangeTiming::Immediate);
+    assert_eq!(s.token_client.balance(&user), 13_000_000);
+    let sub = s.client.get_subscription(&user);
+    assert_eq!(sub.plan_id, 1);
+    assert_eq!(sub.credit_stroops, 1_800_000);
+    assert_eq!(sub.next_renewal_ledger, 146);
+
+    // The credit is consumed by the next renewal.
+    env.ledger().set_sequence_number(146);
+    s.token_client
+        .approve(&user, &s.contract_id, &3_000_000, &1_000);
+    s.client.renew(&s.keeper, &user);
+    assert_eq!(s.token_client.balance(&user), 11_800_000);
+    assert_eq!(s.client.get_subscription(&user).credit_stroops, 0);
+
+    assert!(s
+        .client
+        .try_change_plan(&user, &1, &PlanChangeTiming::Immediate)
+        .is_err());
+}
+
+#[test]
+fn scheduled_plan_change_applies_at_next_renewal() {
+    let env = Env::default();
+    let s = setup(&env);
+    let user = Address::generate(&env);
+    s.token_admin_client.mint(&user, &20_000_000);
+    s.client.create_plan(
+        &s.admin,
+        &1,
+        &String::from_str(&env, "Basic"),
+        &30,
+        &3_000_000,
+    );
+    s.client.create_plan(
+        &s.admin,
+        &2,
+        &String::from_str(&env, "Pro"),
+        &30,
+        &6_000_000,
+    );
+    s.client.subscribe(&user, &1, &None);
+
+    s.client
+        .change_plan(&user, &2, &PlanChangeTiming::NextRenewal);
+    let sub = s.client.get_subscription(&user);
+    assert_eq!(sub.plan_id, 1);
+    assert_eq!(sub.pending_plan_id, Some(2));
+    assert_eq!(s.token_client.balance(&user), 17_000_000);
+
+    env.ledger().set_sequence_number(sub.next_renewal_ledger);
+    s.token_client
+        .approve(&user, &s.contract_id, &6_000_000, &1_000);
+    s.client.renew(&s.keeper, &user);
+    let sub = s.client.get_subscription(&user);
+    assert_eq!(sub.plan_id, 2);
+    assert_eq!(sub.pending_plan_id, None);
+    assert_eq!(s.token_client.balance(&user), 11_000_000);
+}
+
+#[test]
+fn changing_back_to_the_current_plan_drops_the_scheduled_change() {
+    let env = Env::default();
+    let s = setup(&env);
+    let user = Address::generate(&env);
+    s.token_admin_client.mint(&user, &20_000_000);
+    s.client.create_plan(
+        &s.admin,
+        &1,
+        &String::from_str(&env, "Basic"),
+        &30,
+        &3_000_000,
+    );
+    s.client.create_plan(
+        &s.admin,
+        &2,
+        &String::from_str(&env, "Pro"),
+        &30,
+        &6_000_000,
+    );
+    s.client.subscribe(&user, &1, &None);
+    assert_eq!(
+        s.client
+            .try_change_plan(&user, &1, &PlanChangeTiming::NextRenewal),
+        Err(Ok(SubscriptionError::InvalidPlanChange.into()))
+    );
+
+    s.client
+        .change_plan(&user, &2, &PlanChangeTiming::NextRenewal);
+    assert_eq!(s.client.get_active_subscriber_count(&2), 1);
+    s.client
+        .change_plan(&user, &1, &PlanChangeTiming::Immediate);
+    let sub = s.client.get_subscription(&user);
+    assert_eq!(sub.plan_id, 1);
+    assert_eq!(sub.pending_plan_id, None);
+    assert_eq!(s.client.get_active_subscriber_count(&1), 1);
+    assert_eq!(s.client.get_active_subscriber_count(&2), 0);
+    assert_eq!(s.token_client.balance(&user), 17_000_000);
+
+    env.ledger().set_sequence_number(sub.next_renewal_ledger);
+    s.token_client
+        .approve(&user, &s.contract_id, &3_000_000, &1_000);
+    s.client.renew(&s.keeper, &user);
+    let sub = s.client.get_subscription(&user);
+    assert_eq!(sub.plan_id, 1);
+    assert_eq!(s.token_client.balance(&user), 14_000_000);
+}
+
+#[test]
+fn cancel_keeps_access_until_period_end_and_can_be_undone() {
+    let env = Env::default();
+    let s = setup(&env);
+    let user = Address::generate(&env);
+    s.token_admin_client.mint(&user, &10_000_000);
+    s.client.create_plan(
+        &s.admin,
+        &1,
+        &String::from_str(&env, "Starter"),
+        &30,
+        &1_000_000,
+    );
+    s.client.subscribe(&user, &1, &None);
+    let next_renewal_ledger = s.client.get_subscription(&user).next_renewal_ledger;
+
+    s.client.cancel(&user);
+    let sub = s.client.get_subscription(&user);
+    assert!(sub.active);
+    assert_eq!(sub.cancel_at_ledger, Some(next_renewal_ledger));
+    assert!(s.client.try_cancel(&user).is_err());
+    assert!(s.client.try_subscribe(&user, &1, &None).is_err());
+
+    env.ledger().set_sequence_number(next_renewal_ledger - 1);
+    s.client.uncancel(&user);
+    assert_eq!(s.client.get_subscription(&user).cancel_at_ledger, None);
+    assert!(s.client.try_uncancel(&user).is_err());
+
+    // Renewal is refused once the cancellation is reached.
+    s.client.cancel(&user);
+    env.ledger().set_sequence_number(next_renewal_ledger);
+    s.token_client
+        .approve(&user, &s.contract_id, &1_000_000, &1_000);
+    assert!(s.client.try_renew(&s.keeper, &user).is_err());
+    assert!(s.client.try_uncancel(&user).is_err());
+    assert!(!s.client.get_subscription(&user).active);
+    assert_eq!(s.token_client.balance(&user), 9_000_000);
+
+    // Once the paid period is over the subscriber may sign up again.
+    s.client.subscribe(&user, &1, &None);
+    assert!(s.client.get_subscription(&user).active);
+}
+
+#[test]
+fn failed_renewals_go_past_due_then_suspended() {
+    let env = Env::default();
+    let s = setup(&env);
+    let user = Address::generate(&env);
+    s.token_admin_client.mint(&user, &10_000_000);
+    s.client.create_plan(
+        &s.admin,
+        &1,
+        &String::from_str(&env, "Starter"),
+        &30,
+        &1_000_000,
+    );
+    s.client.set_plan_dunning(&s.admin, &1, &0, &2, &5);
+    s.client.subscribe(&user, &1, &None);
+    let due = s.client.get_subscription(&user).next_renewal_ledger;
+
+    // No allowance: the attempt is recorded instead of aborting the call.
+    env.ledger().set_sequence_number(due);
+    assert_eq!(
+        s.client.renew(&s.keeper, &user),
+        SubscriptionStatus::PastDue
+    );
+    let sub = s.client.get_subscription(&user);
+    assert!(sub.active);
+    assert_eq!(sub.failed_renewals, 1);
+    assert_eq!(sub.last_failed_ledger, due);
+    assert_eq!(s.token_client.balance(&user), 9_000_000);
+
+    // Retries are spaced by the plan's retry interval.
+    env.ledger().set_sequence_number(due + 4);
+    assert!(s.client.try_renew(&s.keeper, &user).is_err());
+
+    env.ledger().set_sequence_number(due + 5);
+    assert_eq!(
+        s.client.renew(&s.keeper, &user),
+        SubscriptionStatus::Suspended
+    );
+    let sub = s.client.get_subscription(&user);
+    assert!(!sub.active);
+    assert_eq!(sub.failed_renewals, 2);
+    assert!(s.client.try_renew(&s.keeper, &user).is_err());
+
+    // A suspended subscriber can sign up again.
+    s.client.subscribe(&user, &1, &None);
+    assert_eq!(
+        s.client.get_subscription(&user).status,
+        SubscriptionStatus::Active
+    );
+}
+
+#[test]
+fn past_due_subscription_recovers_or_is_suspended_after_grace() {
+    let env = Env::default();
+    let s = setup(&env);
+    let user = Address::generate(&env);
+    let other = Address::generate(&env);
+    s.token_admin_client.mint(&user, &10_000_000);
+    s.token_admin_client.mint(&other, &10_000_000);
+    s.client.create_plan(
+        &s.admin,
+        &1,
+        &String::from_str(&env, "Starter"),
+        &30,
+        &1_000_000,
+    );
+    s.client.set_plan_dunning(&s.admin, &1, &10, &0, &0);
+    s.client.subscribe(&user, &1, &None);
+    s.client.subscribe(&other, &1, &None);
+    let due = s.client.get_subscription(&user).next_renewal_ledger;
+
+    env.ledger().set_sequence_number(due);
+    assert_eq!(
+        s.client.renew(&s.keeper, &user),
+        SubscriptionStatus::PastDue
+    );
+    assert_eq!(
+        s.client.renew(&s.keeper, &other),
+        SubscriptionStatus::PastDue
+    );
+
+    // Paying within the grace period restores the subscription.
+    env.ledger().set_sequence_number(due + 9);
+    s.token_client
+        .approve(&user, &s.contract_id, &1_000_000, &1_000);
+    assert_eq!(s.client.renew(&s.keeper, &user), SubscriptionStatus::Active);
+    let sub = s.client.get_subscription(&user);
+    assert_eq!(sub.failed_renewals, 0);
+    assert_eq!(sub.next_renewal_ledger, due + 30);
+
+    // Without payment, access ends once the grace period runs out.
+    env.ledger().set_sequence_number(due + 10);
+    let sub = s.client.get_subscription(&other);
+    assert_eq!(sub.status, SubscriptionStatus::Suspended);
+    assert!(!sub.active);
+}
+
+#[test]
+fn renewal_on_retired_plan_expires_subscription() {
+    let env = Env::default();
+    let s = setup(&env);
+    let user = Address::generate(&env);
+    s.token_admin_client.mint(&user, &10_000_000);
+    s.client.create_plan(
+        &s.admin,
+        &1,
+        &String::from_str(&env, "Legacy"),
+        &30,
+        &1_000_000,
+    );
+    s.client.subscribe(&user, &1, &None);
+    s.client.set_plan_status(&s.admin, &1, &false);
+
+    let due = s.client.get_subscription(&user).next_renewal_ledger;
+    env.ledger().set_sequence_number(due);
+    s.token_client
+        .approve(&user, &s.contract_id, &1_000_000, &1_000);
+    assert_eq!(
+        s.client.renew(&s.keeper, &user),
+        SubscriptionStatus::Expired
+    );
+    assert!(!s.client.get_subscription(&user).active);
+    assert_eq!(s.token_client.balance(&user), 9_000_000);
+}
+
+#[test]
+fn renew_many_reports_each_subscriber_without_aborting() {
+    let env = Env::default();
+    let s = setup(&env);
+    s.client.create_plan(
+        &s.admin,
+        &1,
+        &String::from_str(&env, "Monthly"),
+        &30,
+        &1_000_000,
+    );
+    s.client.create_plan(
+        &s.admin,
+        &2,
+        &String::from_str(&env, "Yearly"),
+        &365,
+        &9_000_000,
+    );
+
+    let paying = Address::generate(&env);
+    let no_allowance = Address::generate(&env);
+    let not_due = Address::generate(&env);
+    let unknown = Address::generate(&env);
+    for user in [&paying, &no_allowance, &not_due] {
+        s.token_admin_client.mint(user, &20_000_000);
+    }
+    s.client.subscribe(&paying, &1, &None);
+    s.client.subscribe(&no_allowance, &1, &None);
+    s.client.subscribe(&not_due, &2, &None);
+
+    env.ledger().set_sequence_number(30);
+    s.token_client
+        .approve(&paying, &s.contract_id, &1_000_000, &1_000);
+    let results = s.client.renew_many(
+        &s.keeper,
+        &vec![
+        &env,
+        paying.clone(),
+        no_allowance.clone(),
+        not_due.clone(),
+        unknown.clone(),
+    ]);
+
+    let outcomes: std::vec::Vec<RenewalOutcome> = results.iter().map(|r| r.outcome).collect();
+    assert_eq!(
+        outcomes,
+        [
+            RenewalOutcome::Processed(SubscriptionStatus::Active),
+            RenewalOutcome::Processed(SubscriptionStatus::PastDue),
+            RenewalOutcome::Skipped(SubscriptionError::RenewTooEarly as u32),
+            RenewalOutcome::Skipped(SubscriptionError::SubscriptionNotFound as u32),
+        ]
+    );
+
+    assert_eq!(results.get(3).unwrap().subscriber, unknown);
+    assert_eq!(s.token_client.balance(&paying), 18_000_000);
+    assert_eq!(s.client.get_subscription(&paying).next_renewal_ledger, 60);
+    assert_eq!(s.client.get_subscription(&no_allowance).failed_renewals, 1);
+
+    let mut too_many = Vec::new(&env);
+    for _ in 0..51 {
+        too_many.push_back(Address::generate(&env));
+    }
+    assert!(s.client.try_renew_many(&s.keeper, &too_many).is_err());
+}
+
+#[test]
+fn keeper_reward_is_carved_out_of_renewal_amount() {
+    let env = Env::default();
+    let s = setup(&env);
+    let user = Address::generate(&env);
+    s.token_admin_client.mint(&user, &10_000_000);
+    s.client.create_plan(
+        &s.admin,
+        &1,
+        &String::from_str(&env, "Starter"),
+        &30,
+        &1_000_000,
+    );
+    s.client.set_keeper_reward(
+        &s.admin,
+        &0,
+        &Some(KeeperReward {
+            amount: KeeperRewardAmount::BasisPoints(250),
+            source: KeeperRewardSource::RenewalAmount,
+        }),
+    );
+    s.client.subscribe(&user, &1, &None);
+    let treasury_before = s.token_client.balance(&s.treasury);
+
+    env.ledger().set_sequence_number(30);
+    s.token_client
+        .approve(&user, &s.contract_id, &1_000_000, &1_000);
+    s.client.renew(&s.keeper, &user);
+    assert_eq!(s.token_client.balance(&user), 8_000_000);
+    assert_eq!(s.token_client.balance(&s.keeper), 25_000);
+    assert_eq!(
+        s.token_client.balance(&s.treasury) - treasury_before,
+        975_000
+    );
+    assert_eq!(s.token_client.balance(&s.contract_id), 0);
+
+    // Failed charges pay nothing.
+    env.ledger().set_sequence_number(60);
+    assert_eq!(
+        s.client.renew(&s.keeper, &user),
+        SubscriptionStatus::PastDue
+    );
+    assert_eq!(s.token_client.balance(&s.keeper), 25_000);
+}
+
+#[test]
+fn keeper_reward_can_be_paid_by_treasury() {
+    let env = Env::default();
+    let s = setup(&env);
+    let user = Address::generate(&env);
+    s.token_admin_client.mint(&user, &10_000_000);
+    s.client.create_plan(
+        &s.admin,
+        &1,
+        &String::from_str(&env, "Starter"),
+        &30,
+        &1_000_000,
+    );
+    let reward = KeeperReward {
+        amount: KeeperRewardAmount::Fixed(10_000),
+        source: KeeperRewardSource::Treasury,
+    };
+    s.client.set_keeper_reward(&s.admin, &0, &Some(reward));
+    assert_eq!(s.client.get_keeper_reward(&0), Some(reward));
+    s.client.subscribe(&user, &1, &None);
+    s.token_client
+        .approve(&s.treasury, &s.contract_id, &10_000, &1_000);
+
+    env.ledger().set_sequence_number(30);
+    s.token_client
+        .approve(&user, &s.contract_id, &2_000_000, &1_000);
+    s.client.renew(&s.keeper, &user);
+    assert_eq!(s.token_client.balance(&user), 8_000_000);
+    assert_eq!(s.token_client.balance(&s.keeper), 10_000);
+    assert_eq!(s.token_client.balance(&s.treasury), 1_990_000);
+
+    // An exhausted treasury allowance stops rewards but not renewals.
+    env.ledger().set_sequence_number(60);
+    assert_eq!(s.client.renew(&s.keeper, &user), SubscriptionStatus::Active);
+    assert_eq!(s.token_client.balance(&s.keeper), 10_000);
+    assert_eq!(s.token_client.balance(&s.treasury), 2_990_000);
+
+    let invalid = KeeperReward {
+        amount: KeeperRewardAmount::BasisPoints(10_001),
+        source: KeeperRewardSource::RenewalAmount,
+    };
+    assert!(s
+        .client
+        .try_set_keeper_reward(&s.admin, &0, &Some(invalid))
+        .is_err());
+    s.client.set_keeper_reward(&s.admin, &0, &None);
+    assert_eq!(s.client.get_keeper_reward(&0), None);
+}
+
+/// Rewinds the stored schema version to what a deployment from before
+/// versioning reports.
+fn rewind_schema_version(env: &Env, s: &Setup) {
+    env.as_contract(&s.contract_id, || {
+        env.storage().instance().remove(&DataKey::SchemaVersion);
+    });
+}
+
+#[test]
+fn migrate_rejects_pages_out_of_order() {
+    let env = Env::default();
+    let s = setup(&env);
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    s.token_admin_client.mint(&alice, &10_000_000);
+    s.token_admin_client.mint(&bob, &10_000_000);
+    s.client.create_plan(
+        &s.admin,
+        &1,
+        &String::from_str(&env, "Starter"),
+        &30,
+        &1_000_000,
+    );
+    s.client.subscribe(&alice, &1, &None);
+    s.client.subscribe(&bob, &1, &None);
+    rewind_schema_version(&env, &s);
+
+    // The last page first must not mark the store as migrated.
+    assert_eq!(
+        s.client.try_migrate(&s.admin, &1, &1),
+        Err(Ok(SubscriptionError::MigrationOutOfOrder.into()))
+    );
+    assert_eq!(s.client.schema_version(), 1);
+
+    assert_eq!(s.client.migrate(&s.admin, &0, &1), 1);
+    assert_eq!(
+        s.client.try_migrate(&s.admin, &0, &1),
+        Err(Ok(SubscriptionError::MigrationOutOfOrder.into()))
+    );
+    assert_eq!(
+        s.client.try_migrate(&s.admin, &2, &1),
+        Err(Ok(SubscriptionError::MigrationOutOfOrder.into()))
+    );
+    assert_eq!(s.client.schema_version(), 1);
+
+    assert_eq!(s.client.migrate(&s.admin, &1, &1), 2);
+    assert_eq!(s.client.schema_version(), 2);
+}
+
+#[test]
+fn migrate_bumps_the_version_after_the_last_page() {
+    let env = Env::default();
+    let s = setup(&env);
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    s.token_admin_client.mint(&alice, &10_000_000);
+    s.token_admin_client.mint(&bob, &10_000_000);
+    s.client.create_plan(
+        &s.admin,
+        &1,
+        &String::from_str(&env, "Starter"),
+        &30,
+        &1_000_000,
+    );
+    s.client.subscribe(&alice, &1, &None);
+    s.client.subscribe(&bob, &1, &None);
+    assert_eq!(s.client.schema_version(), 2);
+    assert_eq!(
+        s.client.try_migrate(&s.admin, &0, &1),
+        Err(Ok(SubscriptionError::AlreadyMigrated.into()))
+    );
+
+    rewind_schema_version(&env, &s);
+    assert_eq!(s.client.schema_version(), 1);
+
+    let outsider = Address::generate(&env);
+    assert!(s.client.try_migrate(&outsider, &0, &1).is_err());
+
+    assert_eq!(s.client.migrate(&s.admin, &0, &1), 1);
+    assert_eq!(s.client.migrate(&s.admin, &1, &1), 2);
+    assert_eq!(
+        env.events().all().filter_by_contract(&s.contract_id),
+        [events::Migrated {
+            from_version: 1,
+            to_version: 2,
+            version: events::EVENT_VERSION,
+        }
+        .to_xdr(&env, &s.contract_id)]
+    );
+    assert_eq!(
+        s.client.get_subscription(&alice).status,
+        SubscriptionStatus::Active
+    );
+
+    assert_eq!(
+        s.client.try_migrate(&s.admin, &0, &1),
+        Err(Ok(SubscriptionError::AlreadyMigrated.into()))
+    );
+}
+
+#[test]
+fn only_admin_can_upgrade() {
+    let env = Env::default();
+    let s = setup(&env);
+    let outsider = Address::generate(&env);
+
+    let result = s
+        .client
+        .try_upgrade(&outsider, &BytesN::from_array(&env, &[0; 32]));
+    assert_eq!(result, Err(Ok(SubscriptionError::Unauthorized.into())));
+}
+
+#[test]
+fn admin_transfer_requires_acceptance_by_new_admin() {
+    let env = Env::default();
+    let s = setup(&env);
+    let new_admin = Address::generate(&env);
+    let plan_name = String::from_str(&env, "Starter");
+
+    assert_eq!(
+        s.client.try_accept_admin(),
+        Err(Ok(SubscriptionError::NoPendingAdmin.into()))
+    );
+    assert!(s.client.try_propose_admin(&new_admin, &new_admin).is_err());
+
+    s.client.propose_admin(&s.admin, &new_admin);
+    assert_eq!(s.client.get_pending_admin(), Some(new_admin.clone()));
+    // Nothing changes until the proposal is accepted.
+    assert_eq!(s.client.get_admin(), s.admin);
+    assert!(s
+        .client
+        .try_create_plan(&new_admin, &1, &plan_name, &30, &1_000)
+        .is_err());
+
+    s.client.cancel_admin_proposal(&s.admin);
+    assert_eq!(s.client.get_pending_admin(), None);
+    assert!(s.client.try_accept_admin().is_err());
+
+    s.client.propose_admin(&s.admin, &new_admin);
+    s.client.accept_admin();
+    assert_eq!(
+        env.auths()[0].0,
+        new_admin.clone(),
+        "acceptance must be signed by the proposed admin"
+    );
+    assert_eq!(
+        env.events().all().filter_by_contract(&s.contract_id),
+        [events::AdminChanged {
+            new_admin: new_admin.clone(),
+            previous_admin: s.admin.clone(),
+            version: events::EVENT_VERSION,
+        }
+        .to_xdr(&env, &s.contract_id)]
+    );
+    assert_eq!(s.client.get_admin(), new_admin);
+    assert_eq!(s.client.get_pending_admin(), None);
+
+    s.client
+        .create_plan(&new_admin, &1, &plan_name, &30, &1_000);
+    assert!(s.client.try_set_plan_status(&s.admin, &1, &false).is_err());
+}
+
+#[test]
+fn treasury_changes_at_once_and_token_rotates_at_next_renewal() {
+    let env = Env::default();
+    let s = setup(&env);
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    let new_treasury = Address::generate(&env);
+    let new_token = env
+        .register_stellar_asset_contract_v2(Address::generate(&env))
+        .address();
+    let new_token_admin_client = token::StellarAssetClient::new(&env, &new_token);
+    let new_token_client = token::TokenClient::new(&env, &new_token);
+    s.token_admin_client.mint(&alice, &10_000_000);
+    s.token_admin_client.mint(&bob, &10_000_000);
+    new_token_admin_client.mint(&alice, &10_000_000);
+    s.client.create_plan(
+        &s.admin,
+        &1,
+        &String::from_str(&env, "Starter"),
+        &30,
+        &1_000_000,
+    );
+    s.client.subscribe(&alice, &1, &None);
+
+    s.client.set_treasury(&s.admin, &new_treasury);
+    assert_eq!(s.client.get_treasury(), new_treasury);
+
+    env.ledger().set_sequence_number(5);
+    assert_eq!(
+        s.client
+            .try_schedule_payment_token(&s.admin, &new_token, &4),
+        Err(Ok(SubscriptionError::InvalidEffectiveLedger.into()))
+    );
+    let old_token = s.token_client.address.clone();
+
+    s.client.schedule_payment_token(&s.admin, &new_token, &20);
+    assert_eq!(
+        s.client.get_payment_token_change(),
+        Some(PaymentTokenChange {
+            token: new_token.clone(),
+            effective_ledger: 20,
+        })
+    );
+
+    // Until the effective ledger, new subscriptions still pay in the old token.
+    s.client.subscribe(&bob, &1, &None);
+    assert_eq!(s.client.get_subscription(&bob).payment_token, old_token);
+    assert_eq!(s.token_client.balance(&new_treasury), 1_000_000);
+
+    env.ledger().set_sequence_number(20);
+    assert_eq!(s.client.get_payment_token(), new_token);
+    assert_eq!(s.client.get_payment_token_change(), None);
+    assert_eq!(s.client.get_subscription(&alice).payment_token, old_token);
+
+    let due = s.client.get_subscription(&alice).next_renewal_ledger;
+    env.ledger().set_sequence_number(due);
+    new_token_client.approve(&alice, &s.contract_id, &1_000_000, &1_000);
+    assert_eq!(
+        s.client.renew(&s.keeper, &alice),
+        SubscriptionStatus::Active
+    );
+    assert_eq!(s.client.get_subscription(&alice).payment_token, new_token);
+    assert_eq!(new_token_client.balance(&new_treasury), 1_000_000);
+    assert_eq!(s.token_client.balance(&alice), 9_000_000);
+}
+
+#[test]
+fn credit_is_not_spent_in_a_rotated_token() {
+    let env = Env::default();
+    let s = setup(&env);
+    let alice = Address::generate(&env);
+    let new_token = env
+        .register_stellar_asset_contract_v2(Address::generate(&env))
+        .address();
+    let new_token_client = token::TokenClient::new(&env, &new_token);
+    token::StellarAssetClient::new(&env, &new_token).mint(&alice, &10_000_000);
+    s.token_admin_client.mint(&alice, &10_000_000);
+    let name = String::from_str(&env, "Starter");
+    s.client.create_plan(&s.admin, &1, &name, &30, &2_000_000);
+    s.client.create_plan(&s.admin, &2, &name, &30, &1_000_000);
+    s.client.subscribe(&alice, &1, &None);
+    s.client
+        .change_plan(&alice, &2, &PlanChangeTiming::Immediate);
+    assert_eq!(s.client.get_subscription(&alice).credit_stroops, 1_000_000);
+
+    s.client.schedule_payment_token(&s.admin, &new_token, &10);
+    env.ledger().set_sequence_number(30);
+    new_token_client.approve(&alice, &s.contract_id, &1_000_000, &1_000);
+    assert_eq!(
+        s.client.renew(&s.keeper, &alice),
+        SubscriptionStatus::Active
+    );
+    let subscription = s.client.get_subscription(&alice);
+    assert_eq!(subscription.payment_token, new_token);
+    assert_eq!(subscription.credit_stroops, 0);
+    assert_eq!(new_token_client.balance(&alice), 9_000_000);
+    assert_eq!(new_token_client.balance(&s.treasury), 1_000_000);
+}
+
+#[test]
+fn plans_are_charged_in_their_own_allowlisted_token() {
+    let env = Env::default();
+    let s = setup(&env);
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    let eurc = env
+        .register_stellar_asset_contract_v2(Address::generate(&env))
+        .address();
+    let eurc_admin_client = token::StellarAssetClient::new(&env, &eurc);
+    let eurc_client = token::TokenClient::new(&env, &eurc);
+    eurc_admin_client.mint(&alice, &10_000_000);
+    eurc_admin_client.mint(&bob, &10_000_000);
+    s.token_admin_client.mint(&bob, &10_000_000);
+    s.client.create_plan(
+        &s.admin,
+        &1,
+        &String::from_str(&env, "Starter USD"),
+        &30,
+        &1_000_000,
+    );
+    s.client.create_plan(
+        &s.admin,
+        &2,
+        &String::from_str(&env, "Starter EUR"),
+        &30,
+        &900_000,
+    );
+
+    assert_eq!(
+        s.client
+            .try_set_plan_token(&s.admin, &2, &Some(eurc.clone())),
+        Err(Ok(SubscriptionError::TokenNotAllowed.into()))
+    );
+    s.client.set_token_allowed(&s.admin, &eurc, &true);
+    assert!(s.client.is_token_allowed(&eurc));
+    s.client.set_plan_token(&s.admin, &2, &Some(eurc.clone()));
+    assert_eq!(s.client.get_plan(&2).payment_token, Some(eurc.clone()));
+
+    s.client.subscribe(&alice, &2, &None);
+    s.client.subscribe(&bob, &1, &None);
+    assert_eq!(eurc_client.balance(&s.treasury), 900_000);
+    assert_eq!(s.token_client.balance(&s.treasury), 1_000_000);
+    assert_eq!(s.client.get_subscription(&alice).payment_token, eurc);
+    assert_eq!(
+        s.client
+            .try_change_plan(&alice, &1, &PlanChangeTiming::NextRenewal),
+        Err(Ok(SubscriptionError::InvalidPlanChange.into()))
+    );
+
+    let due = s.client.get_subscription(&alice).next_renewal_ledger;
+    env.ledger().set_sequence_number(due);
+    eurc_client.approve(&alice, &s.contract_id, &900_000, &1_000);
+    assert_eq!(
+        s.client.renew(&s.keeper, &alice),
+        SubscriptionStatus::Active
+    );
+    assert_eq!(eurc_client.balance(&s.treasury), 1_800_000);
+
+    // Delisting stops new sales in the token without touching renewals.
+    s.client.set_token_allowed(&s.admin, &eurc, &false);
+    s.client.cancel(&bob);
+    env.ledger().set_sequence_number(due + 30);
+    assert_eq!(
+        s.client.try_subscribe(&bob, &2, &None),
+        Err(Ok(SubscriptionError::TokenNotAllowed.into()))
+    );
+    eurc_client.approve(&alice, &s.contract_id, &900_000, &1_000);
+    assert_eq!(
+        s.client.renew(&s.keeper, &alice),
+        SubscriptionStatus::Active
+    );
+}
+
+#[test]
+fn merchants_manage_and_are_paid_for_their_own_plans() {
+    let env = Env::default();
+    let s = setup(&env);
+    let merchant_admin = Address::generate(&env);
+    let merchant_treasury = Address::generate(&env);
+    let token = s.token_client.address.clone();
+    let alice = Address::generate(&env);
+    s.token_admin_client.mint(&alice, &10_000_000);
+
+    assert_eq!(
+        s.client.try_register_merchant(
+            &merchant_admin,
+            &7,
+            &merchant_admin,
+            &merchant_treasury,
+            &token
+        ),
+        Err(Ok(SubscriptionError::Unauthorized.into()))
+    );
+    assert_eq!(
+        s.client
+            .try_register_merchant(&s.admin, &0, &merchant_admin, &merchant_treasury, &token),
+        Err(Ok(SubscriptionError::MerchantAlreadyExists.into()))
+    );
+    s.client
+        .register_merchant(&s.admin, &7, &merchant_admin, &merchant_treasury, &token);
+    assert_eq!(s.client.get_merchant(&7).treasury, merchant_treasury);
+    assert_eq!(s.client.get_merchant(&0).admin, s.admin);
+
+    let name = String::from_str(&env, "Starter");
+    s.client.create_plan(&s.admin, &1, &name, &30, &1_000_000);
+    assert_eq!(
+        s.client
+            .try_create_merchant_plan(&s.admin, &7, &name, &30, &500_000),
+        Err(Ok(SubscriptionError::Unauthorized.into()))
+    );
+    // Merchant plans get the next free id; the contract admin still picks.
+    s.client.create_plan(&s.admin, &3, &name, &30, &1_000_000);
+    let plan_id = s
+        .client
+        .create_merchant_plan(&merchant_admin, &7, &name, &30, &500_000);
+    assert_eq!(plan_id, 4);
+    assert_eq!(
+        s.client
+            .create_merchant_plan(&merchant_admin, &7, &name, &30, &500_000),
+        5
+    );
+    assert_eq!(s.client.get_plan(&plan_id).merchant_id, 7);
+
+    // Each admin is confined to its own merchant's plans.
+    assert_eq!(
+        s.client.try_set_plan_status(&s.admin, &plan_id, &false),
+        Err(Ok(SubscriptionError::Unauthorized.into()))
+    );
+    assert_eq!(
+        s.client.try_set_plan_status(&merchant_admin, &1, &false),
+        Err(Ok(SubscriptionError::Unauthorized.into()))
+    );
+    s.client.set_plan_trial(&merchant_admin, &plan_id, &0);
+
+    let merchant_plans = s.client.list_merchant_plans(&7, &0, &10);
+    assert_eq!(merchant_plans.len(), 2);
+    assert_eq!(merchant_plans.get_unchecked(0).id, plan_id);
+    assert_eq!(s.client.list_merchant_plans(&0, &0, &10).len(), 2);
+
+    // Coupon codes are scoped to their merchant, so the same code can mean
+    // different discounts.
+    let code = Symbol::new(&env, "HALF");
+    let terms = CouponTerms {
+        discount: CouponDiscount::BasisPoints(5_000),
+        duration: CouponDuration::Once,
+        max_redemptions: 0,
+        expires_ledger: None,
+        plan_ids: vec![&env],
+    };
+    s.client.create_coupon(&s.admin, &0, &code, &terms);
+    assert_eq!(
+        s.client.try_set_coupon_status(&s.admin, &7, &code, &false),
+        Err(Ok(SubscriptionError::Unauthorized.into()))
+    );
+    s.client.create_coupon(
+        &merchant_admin,
+        &7,
+        &code,
+        &CouponTerms {
+            discount: CouponDiscount::Fixed(100_000),
+            ..terms
+        },
+    );
+    s.client.set_coupon_status(&s.admin, &0, &code, &false);
+
+    s.client.subscribe(&alice, &plan_id, &Some(code.clone()));
+    assert_eq!(s.client.get_coupon(&7, &code).redemptions, 1);
+    assert_eq!(s.client.get_coupon(&0, &code).redemptions, 0);
+    assert_eq!(s.token_client.balance(&merchant_treasury), 400_000);
+    assert_eq!(s.token_client.balance(&s.treasury), 0);
+    assert_eq!(
+        s.client
+            .try_change_plan(&alice, &1, &PlanChangeTiming::Immediate),
+        Err(Ok(SubscriptionError::InvalidPlanChange.into()))
+    );
+
+    // Keeper rewards are set per merchant, and only by its admin.
+    let reward = KeeperReward {
+        amount: KeeperRewardAmount::BasisPoints(10_000),
+        source: KeeperRewardSource::RenewalAmount,
+    };
+    assert_eq!(
+        s.client.try_set_keeper_reward(&s.admin, &7, &Some(reward)),
+        Err(Ok(SubscriptionError::Unauthorized.into()))
+    );
+    s.client.set_keeper_reward(&s.admin, &0, &Some(reward));
+    assert_eq!(s.client.get_keeper_reward(&7), None);
+
+    let new_treasury = Address::generate(&env);
+    s.client
+        .update_merchant(&merchant_admin, &7, &merchant_admin, &new_treasury, &token);
+    let due = s.client.get_subscription(&alice).next_renewal_ledger;
+    env.ledger().set_sequence_number(due);
+    s.token_client
+        .approve(&alice, &s.contract_id, &500_000, &1_000);
+    s.client.renew(&s.keeper, &alice);
+    assert_eq!(s.token_client.balance(&new_treasury), 500_000);
+    assert_eq!(s.token_client.balance(&s.keeper), 0);
+}
+
+#[test]
+fn coupon_discount_applies_at_subscribe_and_renewal_while_it_lasts() {
+    let env = Env::default();
+    let s = setup(&env);
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    let carol = Address::generate(&env);
+    for user in [&alice, &bob, &carol] {
+        s.token_admin_client.mint(user, &10_000_000);
+    }
+    let name = String::from_str(&env, "Starter");
+    s.client.create_plan(&s.admin, &1, &name, &30, &1_000_000);
+    s.client.create_plan(&s.admin, &2, &name, &30, &1_000_000);
+
+    let half = Symbol::new(&env, "HALF");
+    let half_terms = CouponTerms {
+        discount: CouponDiscount::BasisPoints(5_000),
+        duration: CouponDuration::Periods(2),
+        max_redemptions: 1,
+        expires_ledger: Some(100),
+        plan_ids: vec![&env, 1],
+    };
+    assert_eq!(
+        s.client.try_create_coupon(
+            &s.admin,
+            &0,
+            &half,
+            &CouponTerms {
+                discount: CouponDiscount::BasisPoints(0),
+                ..half_terms.clone()
+            }
+        ),
+        Err(Ok(SubscriptionError::InvalidCoupon.into()))
+    );
+    s.client.create_coupon(&s.admin, &0, &half, &half_terms);
+    let welcome = Symbol::new(&env, "WELCOME");
+    s.client.create_coupon(
+        &s.admin,
+        &0,
+        &welcome,
+        &CouponTerms {
+            discount: CouponDiscount::Fixed(200_000),
+            duration: CouponDuration::Forever,
+            max_redemptions: 0,
+            expires_ledger: Some(10),
+            plan_ids: vec![&env],
+        },
+    );
+
+    assert_eq!(
+        s.client.try_subscribe(&alice, &2, &Some(half.clone())),
+        Err(Ok(SubscriptionError::CouponNotRedeemable.into()))
+    );
+    s.client.subscribe(&alice, &1, &Some(half.clone()));
+    assert_eq!(s.token_client.balance(&alice), 9_500_000);
+    assert_eq!(s.client.get_coupon(&0, &half).redemptions, 1);
+    // The redemption cap is reached.
+    assert_eq!(
+        s.client.try_subscribe(&bob, &1, &Some(half.clone())),
+        Err(Ok(SubscriptionError::CouponNotRedeemable.into()))
+    );
+    s.client.subscribe(&bob, &2, &Some(welcome.clone()));
+    assert_eq!(s.token_client.balance(&bob), 9_200_000);
+
+    // Two discounted periods, then full price.
+    s.token_client
+        .approve(&alice, &s.contract_id, &10_000_000, &1_000);
+    for expected_balance in [9_000_000, 8_000_000] {
+        let due = s.client.get_subscription(&alice).next_renewal_ledger;
+        env.ledger().set_sequence_number(due);
+        s.client.renew(&s.keeper, &alice);
+        assert_eq!(s.token_client.balance(&alice), expected_balance);
+    }
+    assert_eq!(s.client.get_subscription(&alice).coupon_code, None);
+
+    // A forever coupon keeps applying after it expires for redemptions.
+    s.token_client
+        .approve(&bob, &s.contract_id, &10_000_000, &1_000);
+    s.client.renew(&s.keeper, &bob);
+    assert_eq!(s.token_client.balance(&bob), 8_400_000);
+    assert_eq!(
+        s.client.try_subscribe(&carol, &2, &Some(welcome)),
+        Err(Ok(SubscriptionError::CouponNotRedeemable.into()))
+    );
+    assert_eq!(
+        s.client
+            .try_subscribe(&carol, &2, &Some(Symbol::new(&env, "NOPE"))),
+        Err(Ok(SubscriptionError::CouponNotFound.into()))
+    );
+}
+
+#[test]
+fn price_updates_are_versioned_and_grandfathered_or_applied_after_notice() {
+    let env = Env::default();
+    let s = setup(&env);
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    for user in [&alice, &bob] {
+        s.token_admin_client.mint(user, &10_000_000);
+        s.token_client
+            .approve(user, &s.contract_id, &10_000_000, &1_000);
+    }
+    let name = String::from_str(&env, "Starter");
+    s.client.create_plan(&s.admin, &1, &name, &30, &1_000_000);
+    s.client.subscribe(&alice, &1, &None);
+
+    let outsider = Address::generate(&env);
+    assert_eq!(
+        s.client.try_update_plan_price(
+            &outsider,
+            &1,
+            &1_500_000,
+            &PriceChangePolicy::Grandfathered
+        ),
+        Err(Ok(SubscriptionError::Unauthorized.into()))
+    );
+    assert_eq!(
+        s.client
+            .try_update_plan_price(&s.admin, &1, &0, &PriceChangePolicy::Grandfathered),
+        Err(Ok(SubscriptionError::InvalidPrice.into()))
+    );
+    assert_eq!(
+        s.client
+            .update_plan_price(&s.admin, &1, &1_500_000, &PriceChangePolicy::Grandfathered),
+        2
+    );
+    assert_eq!(
+        env.events().all().filter_by_contract(&s.contract_id),
+        [events::PlanPriceUpdated {
+            plan_id: 1,
+            price_version: 2,
+            price_stroops: 1_500_000,
+            policy: PriceChangePolicy::Grandfathered,
+            effective_ledger: 0,
+            version: events::EVENT_VERSION,
+        }
+        .to_xdr(&env, &s.contract_id)]
+    );
+
+    // New subscribers pay the new price; alice keeps hers.
+    s.client.subscribe(&bob, &1, &None);
+    assert_eq!(s.token_client.balance(&bob), 8_500_000);
+    assert_eq!(s.client.get_subscription(&bob).price_version, 2);
+    env.ledger().set_sequence_number(30);
+    s.client.renew(&s.keeper, &alice);
+    assert_eq!(s.token_client.balance(&alice), 8_000_000);
+    assert_eq!(s.client.get_subscription(&alice).price_version, 1);
+
+    // Everyone moves to version 3 at their first renewal after the notice.
+    s.client.update_plan_price(
+        &s.admin,
+        &1,
+        &2_000_000,
+        &PriceChangePolicy::NextRenewalAfter(40),
+    );
+    assert_eq!(s.client.get_plan_price(&1, &3).effective_ledger, 70);
+    assert_eq!(s.client.get_plan(&1).price_version, 3);
+    env.ledger().set_sequence_number(60);
+    s.client.renew(&s.keeper, &alice);
+    s.client.renew(&s.keeper, &bob);
+    assert_eq!(s.token_client.balance(&alice), 7_000_000);
+    assert_eq!(s.token_client.balance(&bob), 7_000_000);
+    assert_eq!(s.client.get_subscription(&bob).price_version, 2);
+    env.ledger().set_sequence_number(90);
+    s.client.renew(&s.keeper, &alice);
+    assert_eq!(s.token_client.balance(&alice), 5_000_000);
+    let alice_sub = s.client.get_subscription(&alice);
+    assert_eq!(alice_sub.price_version, 3);
+    assert_eq!(alice_sub.last_charge_stroops, 2_000_000);
+    assert_eq!(
+        s.client.try_get_plan_price(&1, &4),
+        Err(Ok(SubscriptionError::PriceVersionNotFound.into()))
+    );
+}
+
+#[test]
+fn update_plan_changes_descriptive_fields_only() {
+    let env = Env::default();
+    let s = setup(&env);
+    s.client.create_plan(
+        &s.admin,
+        &1,
+        &String::from_str(&env, "Starter"),
+        &30,
+        &1_000_000,
+    );
+
+    let details = PlanDetails {
+        name: String::from_str(&env, "Starter Plus"),
+        description: Some(String::from_str(&env, "Up to 3 projects")),
+        metadata_uri: Some(String::from_str(&env, "ipfs://starter.json")),
+        metadata_hash: Some(BytesN::from_array(&env, &[7; 32])),
+        display_order: 2,
+        max_active_subscribers: 100,
+    };
+    let outsider = Address::generate(&env);
+    assert_eq!(
+        s.client.try_update_plan(&outsider, &1, &details),
+        Err(Ok(SubscriptionError::Unauthorized.into()))
+    );
+    assert_eq!(
+        s.client.try_update_plan(&s.admin, &2, &details),
+        Err(Ok(SubscriptionError::PlanNotFound.into()))
+    );
+    s.client.update_plan(&s.admin, &1, &details);
+    assert_eq!(
+        env.events().all().filter_by_contract(&s.contract_id),
+        [events::PlanUpdated {
+            plan_id: 1,
+            details: details.clone(),
+            version: events::EVENT_VERSION,
+        }
+        .to_xdr(&env, &s.contract_id)]
+    );
+
+    let plan = s.client.get_plan(&1);
+    assert_eq!(plan.name, details.name);
+    assert_eq!(plan.description, details.description);
+    assert_eq!(plan.metadata_uri, details.metadata_uri);
+    assert_eq!(plan.metadata_hash, details.metadata_hash);
+    assert_eq!(plan.display_order, 2);
+    assert_eq!(plan.max_active_subscribers, 100);
+    assert_eq!(plan.period_ledgers, 30);
+    assert_eq!(plan.price_stroops, 1_000_000);
+    assert_eq!(plan.price_version, 1);
+    assert!(plan.active);
+}
+
+#[test]
+fn full_plans_reject_new_subscribers_until_a_seat_frees_up() {
+    let env = Env::default();
+    let s = setup(&env);
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    let carol = Address::generate(&env);
+    for user in [&alice, &bob, &carol] {
+        s.token_admin_client.mint(user, &10_000_000);
+    }
+    let name = String::from_str(&env, "Beta");
+    s.client.create_plan(&s.admin, &1, &name, &30, &1_000_000);
+    s.client.create_plan(&s.admin, &2, &name, &30, &1_000_000);
+    for plan_id in [1, 2] {
+        s.client.update_plan(
+            &s.admin,
+            &plan_id,
+            &PlanDetails {
+                name: name.clone(),
+                description: None,
+                metadata_uri: None,
+                metadata_hash: None,
+                display_order: 0,
+                max_active_subscribers: 2,
+            },
+        );
+    }
+
+    s.client.subscribe(&alice, &1, &None);
+    s.client.subscribe(&bob, &1, &None);
+    assert_eq!(s.client.get_active_subscriber_count(&1), 2);
+    assert_eq!(
+        s.client.try_subscribe(&carol, &1, &None),
+        Err(Ok(SubscriptionError::PlanFull.into()))
+    );
+
+    // Cancelling frees the seat at once, so it can't be taken back while
+    // someone else holds it.
+    s.client.cancel(&alice);
+    assert_eq!(s.client.get_active_subscriber_count(&1), 1);
+    s.client.subscribe(&carol, &1, &None);
+    assert_eq!(
+        s.client.try_uncancel(&alice),
+        Err(Ok(SubscriptionError::PlanFull.into()))
+    );
+
+    // A plan change moves the seat, even when it waits for the renewal.
+    s.client
+        .change_plan(&bob, &2, &PlanChangeTiming::NextRenewal);
+    assert_eq!(s.client.get_active_subscriber_count(&1), 1);
+    assert_eq!(s.client.get_active_subscriber_count(&2), 1);
+    s.client.uncancel(&alice);
+    assert_eq!(s.client.get_active_subscriber_count(&1), 2);
+
+    // Expiry frees the seat too.
+    s.client.set_plan_status(&s.admin, &1, &false);
+    env.ledger().set_sequence_number(30);
+    s.client.renew(&s.keeper, &carol);
+    assert_eq!(
+        s.client.get_subscription(&carol).status,
+        SubscriptionStatus::Expired
+    );
+    assert_eq!(s.client.get_active_subscriber_count(&1), 1);
+}
+
+#[test]
+fn suspension_after_grace_hands_the_seat_to_a_new_subscriber() {
+    let env = Env::default();
+    let s = setup(&env);
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    s.token_admin_client.mint(&alice, &10_000_000);
+    s.token_admin_client.mint(&bob, &10_000_000);
+    let name = String::from_str(&env, "Beta");
+    s.client.create_plan(&s.admin, &1, &name, &30, &1_000_000);
+    s.client.update_plan(
+        &s.admin,
+        &1,
+        &PlanDetails {
+            name,
+            description: None,
+            metadata_uri: None,
+            metadata_hash: None,
+            display_order: 0,
+            max_active_subscribers: 1,
+        },
+    );
+    s.client.set_plan_dunning(&s.admin, &1, &10, &0, &0);
+    s.client.subscribe(&alice, &1, &None);
+
+    // Alice never approved the renewal, so she falls behind and then out of
+    // her grace period.
+    env.ledger().set_sequence_number(30);
+    assert_eq!(
+        s.client.renew(&s.keeper, &alice),
+        SubscriptionStatus::PastDue
+    );
+    env.ledger().set_sequence_number(40);
+    assert_eq!(
+        s.client.try_subscribe(&bob, &1, &None),
+        Err(Ok(SubscriptionError::PlanFull.into()))
+    );
+
+    // Storing the suspension is reported like a failed renewal's.
+    let hook_id = env.register(RecordingHook, ());
+    s.client.set_lifecycle_hook(
+        &s.admin,
+        &0,
+        &Some(LifecycleHook {
+            contract: hook_id.clone(),
+            failure_policy: HookFailurePolicy::Ignore,
+        }),
+    );
+    assert_eq!(
+        s.client.renew(&s.keeper, &alice),
+        SubscriptionStatus::Suspended
+    );
+    assert_eq!(
+        env.events().all().filter_by_contract(&s.contract_id),
+        [events::Suspended {
+            subscriber: alice.clone(),
+            plan_id: 1,
+            ledger: 40,
+            version: events::EVENT_VERSION,
+        }
+        .to_xdr(&env, &s.contract_id)]
+    );
+    assert_eq!(
+        RecordingHookClient::new(&env, &hook_id).received(),
+        vec![&env, (LifecycleEvent::Suspended, alice.clone(), 1, None)]
+    );
+    assert_eq!(s.client.get_active_subscriber_count(&1), 0);
+    assert_eq!(
+        s.client.try_renew(&s.keeper, &alice),
+        Err(Ok(SubscriptionError::SubscriptionLapsed.into()))
+    );
+    s.client.subscribe(&bob, &1, &None);
+    assert_eq!(s.client.get_active_subscriber_count(&1), 1);
+    assert_eq!(
+        s.client.get_subscription(&alice).status,
+        SubscriptionStatus::Suspended
+    );
+}
+
+#[test]
+fn calendar_months_fall_on_the_anchor_day_clamped_to_month_end() {
+    // 2024-01-31T09:30:00Z, then 2024-02-29, 2024-03-31 and 2025-02-28.
+    let jan_31 = 1_706_693_400;
+    let feb_29 = 1_709_199_000;
+    assert_eq!(calendar::add_months(jan_31, 1, jan_31), feb_29);
+    assert_eq!(calendar::add_months(feb_29, 1, jan_31), 1_711_877_400);
+    assert_eq!(calendar::add_months(jan_31, 13, jan_31), 1_740_735_000);
+
+    // 2023-12-15T00:00:00Z, then 2024-01-15 and 2025-01-15.
+    let dec_15 = 1_702_598_400;
+    assert_eq!(calendar::add_months(dec_15, 1, dec_15), 1_705_276_800);
+    assert_eq!(calendar::add_months(dec_15, 13, dec_15), 1_736_899_200);
+}
+
+#[test]
+fn time_based_plans_renew_on_ledger_timestamps() {
+    let env = Env::default();
+    let s = setup(&env);
+    let alice = Address::generate(&env);
+    s.token_admin_client.mint(&alice, &10_000_000);
+    s.token_client
+        .approve(&alice, &s.contract_id, &10_000_000, &1_000_000);
+    s.client.create_plan(
+        &s.admin,
+        &1,
+        &String::from_str(&env, "Monthly"),
+        &30,
+        &1_000_000,
+    );
+
+    let outsider = Address::generate(&env);
+    assert_eq!(
+        s.client
+            .try_set_plan_period(&outsider, &1, &BillingPeriod::Months(1)),
+        Err(Ok(SubscriptionError::Unauthorized.into()))
+    );
+    assert_eq!(
+        s.client
+            .try_set_plan_period(&s.admin, &1, &BillingPeriod::Months(0)),
+        Err(Ok(SubscriptionError::InvalidPeriod.into()))
+    );
+    s.client
+        .set_plan_period(&s.admin, &1, &BillingPeriod::Months(1));
+    assert_eq!(
+        env.events().all().filter_by_contract(&s.contract_id),
+        [events::PlanPeriodChanged {
+            plan_id: 1,
+            period: BillingPeriod::Months(1),
+            period_ledgers: 525_950,
+            version: events::EVENT_VERSION,
+        }
+        .to_xdr(&env, &s.contract_id)]
+    );
+
+    // 2024-01-31T09:30:00Z
+    env.ledger().set_sequence_number(100);
+    env.ledger().set_timestamp(1_706_693_400);
+    s.client.subscribe(&alice, &1, &None);
+    let sub = s.client.get_subscription(&alice);
+    assert_eq!(sub.next_renewal_timestamp, Some(1_709_199_000));
+    assert_eq!(sub.next_renewal_ledger, 100 + 29 * 86_400 / 5);
+
+    // Ledgers closing faster than estimated don't make it due early.
+    env.ledger()
+        .set_sequence_number(sub.next_renewal_ledger + 10);
+    env.ledger().set_timestamp(1_709_198_999);
+    assert_eq!(
+        s.client.try_renew(&s.keeper, &alice),
+        Err(Ok(SubscriptionError::RenewTooEarly.into()))
+    );
+    env.ledger().set_timestamp(1_709_199_000);
+    s.client.renew(&s.keeper, &alice);
+    assert_eq!(s.token_client.balance(&alice), 8_000_000);
+    // 2024-03-31T09:30:00Z, back on the day the subscription started.
+    assert_eq!(
+        s.client.get_subscription(&alice).next_renewal_timestamp,
+        Some(1_711_877_400)
+    );
+
+    // A cancelled subscription keeps access until the renewal timestamp.
+    s.client.cancel(&alice);
+    env.ledger().set_timestamp(1_711_877_399);
+    assert!(s.client.get_subscription(&alice).active);
+    env.ledger().set_timestamp(1_711_877_400);
+    assert_eq!(
+        s.client.get_subscription(&alice).status,
+        SubscriptionStatus::Cancelled
+    );
+}
+
+#[test]
+fn missed_periods_follow_the_plan_catch_up_policy() {
+    let env = Env::default();
+    let s = setup(&env);
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    let carol = Address::generate(&env);
+    let name = String::from_str(&env, "Starter");
+    for (plan_id, user) in [(1, &alice), (2, &bob), (3, &carol)] {
+        s.token_admin_client.mint(user, &10_000_000);
+        s.token_client
+            .approve(user, &s.contract_id, &10_000_000, &1_000);
+        s.client
+            .create_plan(&s.admin, &plan_id, &name, &30, &1_000_000);
+    }
+
+    let outsider = Address::generate(&env);
+    assert_eq!(
+        s.client
+            .try_set_plan_catch_up(&outsider, &1, &CatchUpPolicy::ChargeAll),
+        Err(Ok(SubscriptionError::Unauthorized.into()))
+    );
+    s.client
+        .set_plan_catch_up(&s.admin, &1, &CatchUpPolicy::ChargeAll);
+    assert_eq!(
+        env.events().all().filter_by_contract(&s.contract_id),
+        [events::PlanCatchUpChanged {
+            plan_id: 1,
+            policy: CatchUpPolicy::ChargeAll,
+            version: events::EVENT_VERSION,
+        }
+        .to_xdr(&env, &s.contract_id)]
+    );
+    s.client
+        .set_plan_catch_up(&s.admin, &3, &CatchUpPolicy::Expire);
+    let dave = Address::generate(&env);
+    s.token_admin_client.mint(&dave, &10_000_000);
+    s.token_client
+        .approve(&dave, &s.contract_id, &10_000_000, &1_000);
+    s.client.create_plan(&s.admin, &4, &name, &30, &1_000_000);
+    s.client
+        .set_plan_period(&s.admin, &4, &BillingPeriod::Seconds(150));
+    for (plan_id, user) in [(1, &alice), (2, &bob), (3, &carol), (4, &dave)] {
+        s.client.subscribe(user, &plan_id, &None);
+    }
+
+    // Due at 30; the periods starting at 30 and 60 were missed entirely.
+    env.ledger().set_sequence_number(95);
+    for next_due in [60, 90, 120] {
+        s.client.renew(&s.keeper, &alice);
+        assert_eq!(
+            s.client.get_subscription(&alice).next_renewal_ledger,
+            next_due
+        );
+    }
+    assert_eq!(s.token_client.balance(&alice), 6_000_000);
+
+    s.client.renew(&s.keeper, &bob);
+    assert_eq!(s.client.get_subscription(&bob).next_renewal_ledger, 120);
+    assert_eq!(s.token_client.balance(&bob), 8_000_000);
+    for user in [&alice, &bob] {
+        assert_eq!(
+            s.client.try_renew(&s.keeper, user),
+            Err(Ok(SubscriptionError::RenewTooEarly.into()))
+        );
+    }
+
+    assert_eq!(
+        s.client.renew(&s.keeper, &carol),
+        SubscriptionStatus::Expired
+    );
+    assert_eq!(s.token_client.balance(&carol), 9_000_000);
+
+    // On time-based plans the skipped-to period starts at timestamp 450,
+    // five ledgers ago at the estimated 5 seconds per ledger.
+    env.ledger().set_timestamp(475);
+    s.client.renew(&s.keeper, &dave);
+    assert_eq!(s.token_client.balance(&dave), 8_000_000);
+    let payment = s.client.list_payments(&dave, &1, &1).get_unchecked(0);
+    assert_eq!(payment.period_start_ledger, 90);
+    assert_eq!(payment.period_end_ledger, 120);
+    let sub = s.client.get_subscription(&dave);
+    assert_eq!(sub.next_renewal_timestamp, Some(600));
+    assert_eq!(sub.next_renewal_ledger, 120);
+}
+
+#[test]
+fn paused_subscriptions_skip_renewals_and_resume_with_remaining_time() {
+    let env = Env::default();
+    let s = setup(&env);
+    let alice = Address::generate(&env);
+    s.token_admin_client.mint(&alice, &10_000_000);
+    s.token_client
+        .approve(&alice, &s.contract_id, &10_000_000, &1_000);
+    let name = String::from_str(&env, "Starter");
+    s.client.create_plan(&s.admin, &1, &name, &30, &1_000_000);
+    s.client.subscribe(&alice, &1, &None);
+
+    // Pausing is off until the merchant allows it.
+    assert_eq!(
+        s.client.try_pause(&alice),
+        Err(Ok(SubscriptionError::PauseLimitReached.into()))
+    );
+    let outsider = Address::generate(&env);
+    assert_eq!(
+        s.client.try_set_plan_pause(&outsider, &1, &2, &50),
+        Err(Ok(SubscriptionError::Unauthorized.into()))
+    );
+    s.client.set_plan_pause(&s.admin, &1, &2, &50);
+    assert_eq!(
+        env.events().all().filter_by_contract(&s.contract_id),
+        [events::PlanPauseChanged {
+            plan_id: 1,
+            max_pauses: 2,
+            max_pause_ledgers: 50,
+            version: events::EVENT_VERSION,
+        }
+        .to_xdr(&env, &s.contract_id)]
+    );
+
+    env.ledger().set_sequence_number(10);
+    s.client.pause(&alice);
+    assert_eq!(
+        env.events().all().filter_by_contract(&s.contract_id),
+        [events::Paused {
+            subscriber: alice.clone(),
+            plan_id: 1,
+            ledger: 10,
+            resume_ledger: Some(60),
+            version: events::EVENT_VERSION,
+        }
+        .to_xdr(&env, &s.contract_id)]
+    );
+    let subscription = s.client.get_subscription(&alice);
+    assert_eq!(subscription.status, SubscriptionStatus::Paused);
+    assert!(!subscription.active);
+    assert_eq!(s.client.get_active_subscriber_count(&1), 1);
+
+    // Past the original due date, but nothing is charged while paused.
+    env.ledger().set_sequence_number(40);
+    assert_eq!(
+        s.client.try_renew(&s.keeper, &alice),
+        Err(Ok(SubscriptionError::SubscriptionPaused.into()))
+    );
+    assert_eq!(
+        s.client.try_pause(&alice),
+        Err(Ok(SubscriptionError::SubscriptionPaused.into()))
+    );
+    assert_eq!(
+        s.client.try_cancel(&alice),
+        Err(Ok(SubscriptionError::SubscriptionPaused.into()))
+    );
+
+    // The 20 ledgers left when pausing at 10 run from the resume ledger.
+    s.client.resume(&alice);
+    assert_eq!(
+        env.events().all().filter_by_contract(&s.contract_id),
+        [events::Resumed {
+            subscriber: alice.clone(),
+            plan_id: 1,
+            ledger: 40,
+            next_renewal_ledger: 60,
+            version: events::EVENT_VERSION,
+        }
+        .to_xdr(&env, &s.contract_id)]
+    );
+    assert_eq!(
+        s.client.get_subscription(&alice).status,
+        SubscriptionStatus::Active
+    );
+    assert_eq!(
+        s.client.try_resume(&alice),
+        Err(Ok(SubscriptionError::SubscriptionNotPaused.into()))
+    );
+    assert_eq!(
+        s.client.try_renew(&s.keeper, &alice),
+        Err(Ok(SubscriptionError::RenewTooEarly.into()))
+    );
+    env.ledger().set_sequence_number(60);
+    s.client.renew(&s.keeper, &alice);
+    assert_eq!(s.client.get_subscription(&alice).next_renewal_ledger, 90);
+    assert_eq!(s.token_client.balance(&alice), 8_000_000);
+
+    // A pause left running ends on its own after the plan's maximum.
+    env.ledger().set_sequence_number(70);
+    s.client.pause(&alice);
+    env.ledger().set_sequence_number(130);
+    let subscription = s.client.get_subscription(&alice);
+    assert_eq!(subscription.status, SubscriptionStatus::Active);
+    assert_eq!(subscription.next_renewal_ledger, 140);
+    assert_eq!(
+        s.client.try_pause(&alice),
+        Err(Ok(SubscriptionError::PauseLimitReached.into()))
+    );
+}
+
+#[test]
+fn refunds_are_paid_from_the_treasury_and_recorded() {
+    let env = Env::default();
+    let s = setup(&env);
+    let alice = Address::generate(&env);
+    s.token_admin_client.mint(&alice, &10_000_000);
+    s.token_client
+        .approve(&alice, &s.contract_id, &10_000_000, &1_000);
+    let name = String::from_str(&env, "Starter");
+    s.client.create_plan(&s.admin, &1, &name, &30, &1_000_000);
+    s.client.subscribe(&alice, &1, &None);
+
+    env.ledger().set_sequence_number(10);
+    let outsider = Address::generate(&env);
+    assert_eq!(
+        s.client
+            .try_refund(&outsider, &alice, &RefundAmount::Fixed(250_000)),
+        Err(Ok(SubscriptionError::Unauthorized.into()))
+    );
+    assert_eq!(
+        s.client
+            .try_refund(&s.admin, &alice, &RefundAmount::Fixed(0)),
+        Err(Ok(SubscriptionError::InvalidRefund.into()))
+    );
+
+    assert_eq!(
+        s.client
+            .refund(&s.admin, &alice, &RefundAmount::Fixed(250_000)),
+        250_000
+    );
+    assert!(
+        env.auths()
+            .iter()
+            .any(|(address, _)| *address == s.treasury),
+        "the refund must be signed by the treasury"
+    );
+    assert_eq!(
+        env.events().all().filter_by_contract(&s.contract_id),
+        [events::Refunded {
+            subscriber: alice.clone(),
+            plan_id: 1,
+            token: s.token_client.address.clone(),
+            amount: 250_000,
+            ledger: 10,
+            version: events::EVENT_VERSION,
+        }
+        .to_xdr(&env, &s.contract_id)]
+    );
+    assert_eq!(s.token_client.balance(&alice), 9_250_000);
+    assert_eq!(s.token_client.balance(&s.treasury), 750_000);
+
+    // Half of the period paid at subscribe is left at ledger 15, less what
+    // was already refunded.
+    env.ledger().set_sequence_number(15);
+    assert_eq!(
+        s.client.refund(&s.admin, &alice, &RefundAmount::Prorated),
+        250_000
+    );
+    let subscription = s.client.get_subscription(&alice);
+    assert_eq!(subscription.paid_stroops, 1_000_000);
+    assert_eq!(subscription.refunded_stroops, 500_000);
+    assert_eq!(subscription.status, SubscriptionStatus::Active);
+    assert_eq!(s.token_client.balance(&s.treasury), 500_000);
+
+    // Nothing is left to prorate once access has ended.
+    s.client.cancel(&alice);
+    env.ledger().set_sequence_number(30);
+    assert_eq!(
+        s.client
+            .try_refund(&s.admin, &alice, &RefundAmount::Prorated),
+        Err(Ok(SubscriptionError::InvalidRefund.into()))
+    );
+}
+
+#[test]
+fn refunds_are_capped_at_what_the_period_was_paid() {
+    let env = Env::default();
+    let s = setup(&env);
+    let alice = Address::generate(&env);
+    s.token_admin_client.mint(&alice, &10_000_000);
+    s.token_client
+        .approve(&alice, &s.contract_id, &10_000_000, &1_000);
+    let name = String::from_str(&env, "Starter");
+    s.client.create_plan(&s.admin, &1, &name, &30, &1_000_000);
+    s.client.subscribe(&alice, &1, &None);
+
+    env.ledger().set_sequence_number(10);
+    assert_eq!(
+        s.client
+            .try_refund(&s.admin, &alice, &RefundAmount::Fixed(1_000_001)),
+        Err(Ok(SubscriptionError::InvalidRefund.into()))
+    );
+    assert_eq!(
+        s.client.refund(&s.admin, &alice, &RefundAmount::Prorated),
+        666_666
+    );
+    // The unused value has already been paid back.
+    assert_eq!(
+        s.client
+            .try_refund(&s.admin, &alice, &RefundAmount::Prorated),
+        Err(Ok(SubscriptionError::InvalidRefund.into()))
+    );
+    env.ledger().set_sequence_number(20);
+    assert_eq!(
+        s.client
+            .try_refund(&s.admin, &alice, &RefundAmount::Prorated),
+        Err(Ok(SubscriptionError::InvalidRefund.into()))
+    );
+    assert_eq!(
+        s.client
+            .try_refund(&s.admin, &alice, &RefundAmount::Fixed(333_335)),
+        Err(Ok(SubscriptionError::InvalidRefund.into()))
+    );
+    assert_eq!(
+        s.client
+            .refund(&s.admin, &alice, &RefundAmount::Fixed(333_334)),
+        333_334
+    );
+    assert_eq!(s.token_client.balance(&s.treasury), 0);
+
+    // A renewal starts a new refundable period.
+    env.ledger().set_sequence_number(30);
+    s.client.renew(&s.keeper, &alice);
+    let subscription = s.client.get_subscription(&alice);
+    assert_eq!(subscription.paid_stroops, 1_000_000);
+    assert_eq!(subscription.refunded_stroops, 0);
+    assert_eq!(
+        s.client
+            .refund(&s.admin, &alice, &RefundAmount::Fixed(1_000_000)),
+        1_000_000
+    );
+}
+
+#[test]
+fn refunded_value_is_not_credited_again_by_a_plan_change() {
+    let env = Env::default();
+    let s = setup(&env);
+    let alice = Address::generate(&env);
+    s.token_admin_client.mint(&alice, &10_000_000);
+    s.token_client
+        .approve(&alice, &s.contract_id, &10_000_000, &1_000);
+    s.client.create_plan(
+        &s.admin,
+        &1,
+        &String::from_str(&env, "Starter"),
+        &30,
+        &1_000_000,
+    );
+    s.client.create_plan(
+        &s.admin,
+        &2,
+        &String::from_str(&env, "Pro"),
+        &30,
+        &2_000_000,
+    );
+    s.client.subscribe(&alice, &1, &None);
+
+    env.ledger().set_sequence_number(15);
+    assert_eq!(
+        s.client.refund(&s.admin, &alice, &RefundAmount::Prorated),
+        500_000
+    );
+    s.client
+        .change_plan(&alice, &2, &PlanChangeTiming::Immediate);
+    let subscription = s.client.get_subscription(&alice);
+    assert_eq!(subscription.credit_stroops, 0);
+    assert_eq!(subscription.last_charge_stroops, 2_000_000);
+    assert_eq!(s.token_client.balance(&alice), 7_500_000);
+}
+
+#[test]
+fn charges_are_kept_as_a_paginated_payment_history() {
+    let env = Env::default();
+    let s = setup(&env);
+    let alice = Address::generate(&env);
+    s.token_admin_client.mint(&alice, &10_000_000);
+    s.token_client
+        .approve(&alice, &s.contract_id, &10_000_000, &1_000);
+    let name = String::from_str(&env, "Starter");
+    s.client.create_plan(&s.admin, &1, &name, &30, &1_000_000);
+    s.client.set_plan_trial(&s.admin, &1, &10);
+
+    // The trial is not a charge.
+    s.client.subscribe(&alice, &1, &None);
+    assert_eq!(s.client.get_payment_count(&alice), 0);
+    assert_eq!(s.client.list_payments(&alice, &0, &10), Vec::new(&env));
+
+    env.ledger().set_sequence_number(12);
+    s.client.renew(&s.keeper, &alice);
+    env.ledger().set_sequence_number(40);
+    s.client.renew(&s.keeper, &alice);
+    assert_eq!(s.client.get_payment_count(&alice), 2);
+
+    let payment = |ledger: u32, period_start_ledger: u32| Payment {
+        plan_id: 1,
+        amount: 1_000_000,
+        token: s.token_client.address.clone(),
+        ledger,
+        period_start_ledger,
+        period_end_ledger: period_start_ledger + 30,
+    };
+    assert_eq!(
+        s.client.list_payments(&alice, &0, &10),
+        vec![&env, payment(12, 10), payment(40, 40)]
+    );
+    assert_eq!(
+        s.client.list_payments(&alice, &1, &1),
+        vec![&env, payment(40, 40)]
+    );
+    assert_eq!(s.client.list_payments(&alice, &2, &1), Vec::new(&env));
+    assert_eq!(
+        s.client.try_list_payments(&alice, &0, &51),
+        Err(Ok(SubscriptionError::InvalidPageSize.into()))
+    );
+}
+
+/// A third-party contract unlocking a feature for subscribers of plan 1.
+#[contract]
+struct PremiumFeature;
+
+#[contractimpl]
+impl PremiumFeature {
+    pub fn unlock(env: Env, subscriptions: Address, user: Address) -> bool {
+        SbSubscriptionClient::new(&env, &subscriptions).is_entitled(&user, &Some(1))
+    }
+}
+
+#[test]
+fn other_contracts_gate_features_on_entitlement() {
+    let env = Env::default();
+    let s = setup(&env);
+    let gate = PremiumFeatureClient::new(&env, &env.register(PremiumFeature, ()));
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    let carol = Address::generate(&env);
+    // Alice can only pay for her first period.
+    s.token_admin_client.mint(&alice, &1_000_000);
+    for user in [&alice, &bob, &carol] {
+        s.token_admin_client.mint(user, &10_000_000);
+        s.token_client
+            .approve(user, &s.contract_id, &10_000_000, &1_000);
+    }
+    let name = String::from_str(&env, "Premium");
+    s.client.create_plan(&s.admin, &1, &name, &30, &11_000_000);
+    s.client.set_plan_dunning(&s.admin, &1, &10, &0, &0);
+    let name = String::from_str(&env, "Basic");
+    s.client.create_plan(&s.admin, &2, &name, &30, &1_000_000);
+    s.client.set_plan_pause(&s.admin, &2, &1, &0);
+
+    assert!(!gate.unlock(&s.contract_id, &alice));
+    assert_eq!(s.client.access_until(&alice), None);
+    s.client.subscribe(&alice, &1, &None);
+    s.client.subscribe(&bob, &2, &None);
+    s.client.subscribe(&carol, &2, &None);
+
+    assert!(gate.unlock(&s.contract_id, &alice));
+    assert!(!gate.unlock(&s.contract_id, &bob));
+    assert!(s.client.is_entitled(&bob, &None));
+    // Unpaid renewals keep access through the grace period.
+    assert_eq!(s.client.access_until(&alice), Some(40));
+    assert_eq!(s.client.access_until(&bob), Some(30));
+
+    env.ledger().set_sequence_number(5);
+    s.client.cancel(&bob);
+    s.client.pause(&carol);
+    assert!(s.client.is_entitled(&bob, &Some(2)));
+    assert!(!s.client.is_entitled(&carol, &None));
+    assert_eq!(s.client.access_until(&carol), None);
+    env.ledger().set_sequence_number(10);
+    s.client.resume(&carol);
+    assert_eq!(s.client.access_until(&carol), Some(35));
+
+    env.ledger().set_sequence_number(30);
+    assert_eq!(
+        s.client.renew(&s.keeper, &alice),
+        SubscriptionStatus::PastDue
+    );
+    assert!(!s.client.is_entitled(&bob, &None));
+    assert_eq!(s.client.access_until(&bob), None);
+    env.ledger().set_sequence_number(39);
+    assert!(gate.unlock(&s.contract_id, &alice));
+    env.ledger().set_sequence_number(40);
+    assert!(!gate.unlock(&s.contract_id, &alice));
+    assert_eq!(s.client.access_until(&alice), None);
+}
+
+/// A merchant contract that records the lifecycle events it receives and can
+/// be made to fail.
+#[contract]
+struct RecordingHook;
+
+#[contractimpl]
+impl RecordingHook {
+    pub fn set_failing(env: Env, failing: bool) {
+        env.storage()
+            .instance()
+            .set(&Symbol::new(&env, "failing"), &failing);
+    }
+
+    pub fn received(env: Env) -> Vec<(LifecycleEvent, Address, u32, Option<u32>)> {
+        env.storage()
+            .instance()
+            .get(&Symbol::new(&env, "received"))
+            .unwrap_or(Vec::new(&env))
+    }
+}
+
+#[contractimpl]
+impl LifecycleHookInterface for RecordingHook {
+    fn on_lifecycle_event(
+        env: Env,
+        event: LifecycleEvent,
+        subscriber: Address,
+        plan_id: u32,
+        access_until: Option<u32>,
+    ) {
+        let storage = env.storage().instance();
+        if storage.get(&Symbol::new(&env, "failing")).unwrap_or(false) {
+            panic!("hook failure");
+        }
+        let mut received = Self::received(env.clone());
+        received.push_back((event, subscriber, plan_id, access_until));
+        storage.set(&Symbol::new(&env, "received"), &received);
+    }
+}
+
+#[test]
+fn lifecycle_hook_follows_subscriptions_under_its_failure_policy() {
+    let env = Env::default();
+    let s = setup(&env);
+    let hook_id = env.register(RecordingHook, ());
+    let hook = RecordingHookClient::new(&env, &hook_id);
+    let alice = Address::generate(&env);
+    let bob = Address::generate(&env);
+    for user in [&alice, &bob] {
+        s.token_admin_client.mint(user, &10_000_000);
+        s.token_client
+            .approve(user, &s.contract_id, &10_000_000, &1_000);
+    }
+    let name = String::from_str(&env, "Starter");
+    s.client.create_plan(&s.admin, &1, &name, &30, &1_000_000);
+    s.client.create_plan(&s.admin, &2, &name, &30, &1_000_000);
+
+    let reverting = LifecycleHook {
+        contract: hook_id.clone(),
+        failure_policy: HookFailurePolicy::Revert,
+    };
+    let outsider = Address::generate(&env);
+    assert_eq!(
+        s.client
+            .try_set_lifecycle_hook(&outsider, &0, &Some(reverting.clone())),
+        Err(Ok(SubscriptionError::Unauthorized.into()))
+    );
+    s.client
+        .set_lifecycle_hook(&s.admin, &0, &Some(reverting.clone()));
+    assert_eq!(
+        env.events().all().filter_by_contract(&s.contract_id),
+        [events::LifecycleHookChanged {
+            merchant_id: 0,
+            hook: Some(reverting.clone()),
+            version: events::EVENT_VERSION,
+        }
+        .to_xdr(&env, &s.contract_id)]
+    );
+    assert_eq!(s.client.get_lifecycle_hook(&0), Some(reverting));
+
+    s.client.subscribe(&alice, &1, &None);
+    s.client.subscribe(&bob, &2, &None);
+    env.ledger().set_sequence_number(30);
+    s.client.renew(&s.keeper, &alice);
+    s.client.set_plan_status(&s.admin, &2, &false);
+    assert_eq!(s.client.renew(&s.keeper, &bob), SubscriptionStatus::Expired);
+    assert_eq!(
+        hook.received(),
+        vec![
+            &env,
+            (LifecycleEvent::Subscribed, alice.clone(), 1, Some(30)),
+            (LifecycleEvent::Subscribed, bob.clone(), 2, Some(30)),
+            (LifecycleEvent::Renewed, alice.clone(), 1, Some(60)),
+            (LifecycleEvent::Expired, bob.clone(), 2, None),
+        ]
+    );
+
+    // Under `Revert` a failing hook turns charges down...
+    hook.set_failing(&true);
+    assert_eq!(
+        s.client.try_subscribe(&bob, &1, &None),
+        Err(Ok(SubscriptionError::HookFailed.into()))
+    );
+
+    // ...but can't keep a subscriber from cancelling.
+    s.client.cancel(&alice);
+    assert_eq!(s.client.get_subscription(&alice).cancel_at_ledger, Some(60));
+    s.client.uncancel(&alice);
+
+    // A turned-down renewal gives the fee back, and batches go on past it.
+    env.ledger().set_sequence_number(60);
+    let alice_balance = s.token_client.balance(&alice);
+    let treasury_balance = s.token_client.balance(&s.treasury);
+    assert_eq!(
+        s.client.try_renew(&s.keeper, &alice),
+        Err(Ok(SubscriptionError::HookFailed.into()))
+    );
+    let results = s.client.renew_many(&s.keeper, &vec![&env, alice.clone()]);
+    assert_eq!(
+        results.get_unchecked(0).outcome,
+        RenewalOutcome::Skipped(SubscriptionError::HookFailed as u32)
+    );
+    assert_eq!(s.token_client.balance(&alice), alice_balance);
+    assert_eq!(s.token_client.balance(&s.treasury), treasury_balance);
+    assert_eq!(s.token_client.balance(&s.contract_id), 0);
+    assert_eq!(s.client.get_subscription(&alice).next_renewal_ledger, 60);
+    assert_eq!(s.client.get_payment_count(&alice), 2);
+
+    // Under `Ignore` the failure is skipped.
+    s.client.set_lifecycle_hook(
+        &s.admin,
+        &0,
+        &Some(LifecycleHook {
+            contract: hook_id,
+            failure_policy: HookFailurePolicy::Ignore,
+        }),
+    );
+    s.client.renew(&s.keeper, &alice);
+    assert_eq!(s.client.get_subscription(&alice).next_renewal_ledger, 90);
+    assert_eq!(
+        s.token_client.balance(&s.treasury),
+        treasury_balance + 1_000_000
+    );
+    assert_eq!(hook.received().len(), 4);
+
+    hook.set_failing(&false);
+    s.client.set_lifecycle_hook(&s.admin, &0, &None);
+    assert_eq!(s.client.get_lifecycle_hook(&0), None);
+    s.client.cancel(&alice);
+    assert_eq!(hook.received().len(), 4);
+}